@@ -1,22 +1,43 @@
 mod modules {
+    pub mod animation;
+    pub mod atlas;
     pub mod category;
+    pub mod codecs;
     pub mod config;
     pub mod db;
+    pub mod deepzoom;
+    pub mod exifinfo;
     pub mod fileassoc;
     pub mod filecache;
     pub mod filehandler;
+    pub mod filequery;
+    pub mod hashindex;
     pub mod imagedupe;
     pub mod imgoptimize;
     pub mod logger;
+    pub mod mediatype;
     pub mod pack;
     pub mod pathutils;
+    pub mod placeholder;
+    pub mod protocol;
+    pub mod raw;
     pub mod stats;
+    pub mod thumbcache;
+    pub mod thumbjobs;
+    pub mod watcher;
 }
 
+use crate::modules::animation::extract_frames;
+use crate::modules::atlas::build_thumbnail_atlas;
 use crate::modules::db::{create_category_tags, migrate_database};
+use crate::modules::exifinfo::get_image_exif;
 use crate::modules::fileassoc::register_lkrz_association;
-use crate::modules::imgoptimize::start_cache_cleanup;
 use crate::modules::pack::{cancel_export_pack, export_category_pack, import_category_pack};
+use crate::modules::protocol::{handle_lockerz_protocol, SCHEME};
+use crate::modules::thumbjobs::{
+    cancel_thumbnail_jobs, reprioritize_thumbnail_jobs, submit_thumbnail_jobs,
+};
+use crate::modules::watcher::start_library_watcher;
 use modules::{
     category::create_category,
     category::delete_category,
@@ -32,8 +53,9 @@ use modules::{
     filehandler::move_file,
     filehandler::move_file_category,
     filehandler::save_and_move_file,
-    imagedupe::find_duplicates,
+    imagedupe::{cancel_duplicate_scan, find_duplicates, find_similar_images},
     imgoptimize::batch_optimize_images,
+    imgoptimize::get_thumbnail_cache_stats,
     imgoptimize::handle_optimize_image_request,
     logger::LOGGER,
     stats::get_stats,
@@ -70,22 +92,26 @@ fn show_in_photos(path: String) {
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
-    let _ = setup_folders();
     let _ = init_db();
     log_pre!("Application started");
     if let Err(e) = migrate_database() {
         log_error!("Database migration failed: {}", e);
     }
+    // The file index reconciles against the DB, so the schema must be current first
+    let _ = setup_folders();
     if let Err(e) = create_category_tags() {
         log_error!("Failed to create category tags: {}", e);
     }
-    start_cache_cleanup();
 
     tauri::Builder::default()
         .plugin(tauri_plugin_clipboard_manager::init())
         .plugin(tauri_plugin_dialog::init())
+        .register_asynchronous_uri_scheme_protocol(SCHEME, |_ctx, request, responder| {
+            handle_lockerz_protocol(request, responder)
+        })
         .setup(|app| {
             register_lkrz_association(app.handle());
+            start_library_watcher(app.handle());
             let window = app.get_webview_window("main").unwrap();
             window.on_window_event(move |event| {
                 if let tauri::WindowEvent::Destroyed { .. } = event {
@@ -114,7 +140,16 @@ pub fn run() {
             get_files,
             get_stats,
             find_duplicates,
+            find_similar_images,
+            cancel_duplicate_scan,
             batch_optimize_images,
+            get_thumbnail_cache_stats,
+            build_thumbnail_atlas,
+            extract_frames,
+            get_image_exif,
+            submit_thumbnail_jobs,
+            reprioritize_thumbnail_jobs,
+            cancel_thumbnail_jobs,
             Database::remove_image_tag,
             Database::get_all_tags,
             Database::search_images_by_tags,
@@ -123,6 +158,7 @@ pub fn run() {
             Database::add_tag,
             Database::add_image,
             Database::get_image_id,
+            Database::set_image_rating,
             Database::remove_tag,
             Database::edit_tag,
             Database::create_category_tags,
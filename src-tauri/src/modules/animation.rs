@@ -0,0 +1,138 @@
+use crate::modules::config::get_config;
+use crate::modules::filecache::{get_or_init_cache, FileInfo};
+use crate::modules::mediatype::{self, MediaFormat, MediaKind};
+use crate::modules::pathutils::get_main_path;
+use crate::log_info;
+use image::codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder};
+use image::{AnimationDecoder, DynamicImage, Frame, Frames};
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use tokio::task;
+
+// Animated GIF, WebP and APNG. The `image` decoders hand out frames already
+// composited onto the full canvas, so every frame can be resized or saved
+// on its own without tracking disposal modes.
+
+// Browsers play delays of 10 ms or less at 100 ms (a legacy of GIFs that
+// set 0 and expect the default); follow them so reported durations and
+// previews match what the user sees everywhere else.
+const MIN_DELAY_MS: u32 = 10;
+const DEFAULT_DELAY_MS: u32 = 100;
+
+#[derive(Debug, Serialize, Clone, Copy)]
+pub struct AnimationInfo {
+    pub frame_count: u32,
+    pub duration_ms: u64,
+}
+
+/// Iterates the composited frames of an animated image.
+pub fn open_frames(path: &Path) -> Result<Frames<'static>, Box<dyn std::error::Error + Send + Sync>> {
+    let reader = BufReader::new(File::open(path)?);
+    let frames = match mediatype::detect(path).format {
+        Some(MediaFormat::Gif) => GifDecoder::new(reader)?.into_frames(),
+        Some(MediaFormat::WebP) => WebPDecoder::new(reader)?.into_frames(),
+        Some(MediaFormat::Png) => PngDecoder::new(reader)?.apng()?.into_frames(),
+        _ => return Err(format!("Not an animated image: {}", path.display()).into()),
+    };
+    Ok(frames)
+}
+
+/// How long `frame` stays on screen, with browser clamping applied.
+pub fn delay_ms(frame: &Frame) -> u32 {
+    let (numer, denom) = frame.delay().numer_denom_ms();
+    let ms = (numer as f64 / denom.max(1) as f64).round() as u32;
+    if ms <= MIN_DELAY_MS {
+        DEFAULT_DELAY_MS
+    } else {
+        ms
+    }
+}
+
+/// Counts frames and sums their delays. Decodes every frame, so this
+/// belongs in background passes rather than request paths.
+pub fn info(path: &Path) -> Result<AnimationInfo, Box<dyn std::error::Error + Send + Sync>> {
+    let mut info = AnimationInfo { frame_count: 0, duration_ms: 0 };
+    for frame in open_frames(path)? {
+        let frame = frame?;
+        info.frame_count += 1;
+        info.duration_ms += delay_ms(&frame) as u64;
+    }
+    Ok(info)
+}
+
+/// Saves frames of an animated image as PNG stills in `category`, named
+/// `<source stem>_f<frame number>.png`. `frames` holds zero-based indices;
+/// without it every frame is extracted. Returns the new files.
+#[tauri::command]
+pub async fn extract_frames(
+    path: String,
+    category: String,
+    frames: Option<Vec<u32>>,
+) -> Result<Vec<FileInfo>, String> {
+    task::spawn_blocking(move || extract(Path::new(&path), &category, frames.as_deref()))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+fn extract(source: &Path, category: &str, wanted: Option<&[u32]>) -> Result<Vec<FileInfo>, String> {
+    if mediatype::detect(source).kind != MediaKind::AnimatedImage {
+        return Err(format!("Not an animated image: {}", source.display()));
+    }
+
+    let root_folder_path = get_config().folderPath;
+    let main_path = get_main_path().map_err(|e| format!("Failed to get main path: {}", e))?;
+    let cache = get_or_init_cache(main_path.join("cache")).map_err(|e| format!("Failed to get cache: {}", e))?;
+    let category_path = root_folder_path.join(category);
+    fs::create_dir_all(&category_path).map_err(|e| format!("Error creating directory: {}", e))?;
+
+    let stem = source
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "frame".to_string());
+    let last_wanted = wanted.and_then(|w| w.iter().max().copied());
+
+    let mut files = Vec::new();
+    let frames = open_frames(source).map_err(|e| format!("Failed to decode {}: {}", source.display(), e))?;
+    for (index, frame) in frames.enumerate() {
+        let index = index as u32;
+        if last_wanted.is_some_and(|last| index > last) {
+            break;
+        }
+        if wanted.is_some_and(|w| !w.contains(&index)) {
+            continue;
+        }
+
+        let frame = frame.map_err(|e| format!("Failed to decode frame {}: {}", index, e))?;
+        let target = unique_path(&category_path, &format!("{}_f{:04}", stem, index + 1), "png");
+        DynamicImage::ImageRgba8(frame.into_buffer())
+            .save(&target)
+            .map_err(|e| format!("Failed to save frame {}: {}", index, e))?;
+
+        let id = cache
+            .upsert_file(&root_folder_path, category, &target)
+            .map_err(|e| format!("Error updating file index: {}", e))?;
+        let metadata = fs::metadata(&target).map_err(|e| format!("Error getting file metadata: {}", e))?;
+        let name = target.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        let mut file_info = cache
+            .create_file_info(name, category.to_string(), &target, &metadata, &root_folder_path)
+            .map_err(|e| format!("Error creating file info: {}", e))?;
+        file_info.id = id;
+        files.push(file_info);
+    }
+
+    log_info!("Extracted {} frames from {} into {}", files.len(), source.display(), category);
+    Ok(files)
+}
+
+// Never overwrites: adds `-2`, `-3`, ... until the name is free.
+fn unique_path(dir: &Path, stem: &str, ext: &str) -> PathBuf {
+    let mut candidate = dir.join(format!("{}.{}", stem, ext));
+    let mut n = 2;
+    while candidate.exists() {
+        candidate = dir.join(format!("{}-{}.{}", stem, n, ext));
+        n += 1;
+    }
+    candidate
+}
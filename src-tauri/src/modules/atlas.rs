@@ -0,0 +1,238 @@
+use crate::modules::imgoptimize::{encode, load_keyed, thumb_spec};
+use crate::modules::thumbcache::{self, Background, ThumbKey, ThumbSpec};
+use base64::{engine::general_purpose, Engine as _};
+use image::{imageops, DynamicImage, GenericImageView, Rgb, RgbImage, Rgba, RgbaImage};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::Path;
+use tokio::task;
+
+// A sprite atlas packs the thumbnails of one grid page into a single image,
+// so a page costs one IPC round-trip instead of one per item. Each member
+// is rendered through the normal thumbnail pipeline (and its caches), then
+// placed with a shelf packer.
+//
+// Atlases live in the thumbnail disk store under a key derived from every
+// member's path and content-addressed thumbnail key. Editing, moving or
+// removing any member changes that key, so a stale page is never served;
+// the old entry ages out through LRU eviction like any other thumbnail.
+//
+// A stored entry is `<layout length: u32 LE><layout JSON><image bytes>`,
+// keeping the rectangles and pixels in one atomic write.
+
+// Gap between sprites so scaled-down rendering doesn't bleed neighbours in.
+const PADDING: u32 = 2;
+
+// Upper bound on the atlas width; pages wider than this wrap into more rows.
+const MAX_WIDTH: u32 = 8192;
+
+#[derive(Debug, Deserialize)]
+pub struct AtlasRequest {
+    pub paths: Vec<String>,
+    pub profile: Option<String>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub quality: Option<i32>,
+    pub format: Option<String>,
+    pub background: Option<String>,
+    pub fit: Option<String>,
+    /// Study view, e.g. `grayscale` or `posterize-3,mirror`.
+    pub view: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct AtlasRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+struct AtlasLayout {
+    width: u32,
+    height: u32,
+    rects: HashMap<String, AtlasRect>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AtlasResult {
+    /// Changes whenever any member changes; usable as a cache key on the frontend.
+    pub key: String,
+    /// The packed image, base64 encoded.
+    pub data: String,
+    pub width: u32,
+    pub height: u32,
+    /// Sprite position per member path.
+    pub rects: HashMap<String, AtlasRect>,
+    /// Members that could not be rendered, with the reason.
+    pub failed: HashMap<String, String>,
+}
+
+/// Builds (or loads from cache) the atlas for one page of files.
+#[tauri::command]
+pub async fn build_thumbnail_atlas(request: AtlasRequest) -> Result<AtlasResult, String> {
+    let spec = thumb_spec(
+        request.profile.as_deref(),
+        request.width,
+        request.height,
+        request.quality,
+        request.format.as_deref(),
+        request.background.as_deref(),
+        request.fit.as_deref(),
+        request.view.as_deref(),
+    )?;
+
+    task::spawn_blocking(move || build_atlas(&request.paths, &spec))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+fn build_atlas(
+    paths: &[String],
+    spec: &ThumbSpec,
+) -> Result<AtlasResult, Box<dyn std::error::Error + Send + Sync>> {
+    let mut failed = HashMap::new();
+    let mut members = Vec::new();
+    let mut hasher = Sha256::new();
+    for path in paths {
+        match thumbcache::key_for(Path::new(path), spec) {
+            Ok(key) => {
+                hasher.update(path.as_bytes());
+                hasher.update([0]);
+                hasher.update(key.as_str().as_bytes());
+                hasher.update([b'\n']);
+                members.push((path.clone(), key));
+            }
+            Err(e) => {
+                failed.insert(path.clone(), e.to_string());
+            }
+        }
+    }
+    let key = ThumbKey::new(&format!("atlas{:x}", hasher.finalize()), spec);
+
+    if let Some((layout, image)) = thumbcache::load(&key).and_then(|blob| unpack(&blob)) {
+        return Ok(result(&key, layout, &image, failed));
+    }
+
+    let rendered: Vec<(String, Result<DynamicImage, String>)> = members
+        .into_par_iter()
+        .map(|(path, key)| {
+            let image = load_keyed(&path, key, spec)
+                .map_err(|e| e.to_string())
+                .and_then(|bytes| image::load_from_memory(&bytes).map_err(|e| e.to_string()));
+            (path, image)
+        })
+        .collect();
+
+    let mut sprites = Vec::new();
+    for (path, image) in rendered {
+        match image {
+            Ok(image) => sprites.push((path, image)),
+            Err(e) => {
+                failed.insert(path, e);
+            }
+        }
+    }
+
+    let layout = pack(&sprites);
+    let transparent = sprites.iter().any(|(_, image)| image.color().has_alpha());
+    let canvas = compose(&layout, &sprites, transparent, spec.background);
+    let image = encode(&canvas, spec.format, transparent, spec.quality)?;
+
+    // A page with failures is rebuilt next time in case they were transient
+    if failed.is_empty() {
+        let layout_json = serde_json::to_vec(&layout)?;
+        let mut blob = Vec::with_capacity(4 + layout_json.len() + image.len());
+        blob.extend_from_slice(&(layout_json.len() as u32).to_le_bytes());
+        blob.extend_from_slice(&layout_json);
+        blob.extend_from_slice(&image);
+        thumbcache::store(&key, &blob);
+    }
+
+    Ok(result(&key, layout, &image, failed))
+}
+
+// Shelf packing: tallest sprites first, left to right, starting a new row
+// when the next one doesn't fit. The width aims for a roughly square atlas.
+fn pack(sprites: &[(String, DynamicImage)]) -> AtlasLayout {
+    if sprites.is_empty() {
+        return AtlasLayout::default();
+    }
+
+    let area: u64 = sprites
+        .iter()
+        .map(|(_, img)| (img.width() + PADDING) as u64 * (img.height() + PADDING) as u64)
+        .sum();
+    let widest = sprites.iter().map(|(_, img)| img.width()).max().unwrap_or(0);
+    let width = ((area as f64).sqrt().ceil() as u32).clamp(widest, MAX_WIDTH.max(widest));
+
+    let mut order: Vec<usize> = (0..sprites.len()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(sprites[i].1.height()));
+
+    let mut rects = HashMap::new();
+    let (mut x, mut y, mut row_height, mut used_width) = (0, 0, 0, 0);
+    for i in order {
+        let (path, img) = &sprites[i];
+        let (w, h) = img.dimensions();
+        if x > 0 && x + w > width {
+            x = 0;
+            y += row_height + PADDING;
+            row_height = 0;
+        }
+        rects.insert(path.clone(), AtlasRect { x, y, width: w, height: h });
+        used_width = used_width.max(x + w);
+        row_height = row_height.max(h);
+        x += w + PADDING;
+    }
+
+    AtlasLayout { width: used_width, height: y + row_height, rects }
+}
+
+fn compose(
+    layout: &AtlasLayout,
+    sprites: &[(String, DynamicImage)],
+    transparent: bool,
+    background: Background,
+) -> DynamicImage {
+    let (w, h) = (layout.width.max(1), layout.height.max(1));
+    if transparent {
+        let mut canvas = RgbaImage::from_pixel(w, h, Rgba([0, 0, 0, 0]));
+        for (path, img) in sprites {
+            let rect = layout.rects[path];
+            imageops::replace(&mut canvas, &img.to_rgba8(), rect.x as i64, rect.y as i64);
+        }
+        DynamicImage::ImageRgba8(canvas)
+    } else {
+        let fill = match background {
+            Background::Color(color) => color,
+            _ => [255, 255, 255],
+        };
+        let mut canvas = RgbImage::from_pixel(w, h, Rgb(fill));
+        for (path, img) in sprites {
+            let rect = layout.rects[path];
+            imageops::replace(&mut canvas, &img.to_rgb8(), rect.x as i64, rect.y as i64);
+        }
+        DynamicImage::ImageRgb8(canvas)
+    }
+}
+
+fn unpack(blob: &[u8]) -> Option<(AtlasLayout, Vec<u8>)> {
+    let len = u32::from_le_bytes(blob.get(..4)?.try_into().ok()?) as usize;
+    let layout = serde_json::from_slice(blob.get(4..4 + len)?).ok()?;
+    Some((layout, blob[4 + len..].to_vec()))
+}
+
+fn result(key: &ThumbKey, layout: AtlasLayout, image: &[u8], failed: HashMap<String, String>) -> AtlasResult {
+    AtlasResult {
+        key: key.as_str().to_string(),
+        data: general_purpose::STANDARD.encode(image),
+        width: layout.width,
+        height: layout.height,
+        rects: layout.rects,
+        failed,
+    }
+}
@@ -1,11 +1,11 @@
 use crate::modules::config::get_config;
 use crate::modules::db::connect_db;
-use crate::modules::filecache::FileCache;
+use crate::modules::filecache::{get_or_init_cache, list_category_dirs, FileCache};
 use crate::modules::imgoptimize::evict_path_prefix;
+use crate::modules::pathutils::get_main_path;
 use crate::{log_error, log_info};
 use serde::Serialize;
-use std::path::{Path, PathBuf};
-use tokio::fs;
+use std::path::PathBuf;
 use tokio::task;
 
 #[derive(Serialize)]
@@ -15,69 +15,27 @@ pub struct Category {
     pub size: u64,
 }
 
-async fn get_dir_stats_async(
-    dir_path: &Path,
-) -> Result<(u64, usize), Box<dyn std::error::Error + Send + Sync>> {
-    let mut size: u64 = 0;
-    let mut count: usize = 0;
-
-    let mut read_dir = fs::read_dir(dir_path).await.map_err(|e| e.to_string())?;
-
-    while let Some(entry) = read_dir.next_entry().await.map_err(|e| e.to_string())? {
-        let entry_path = entry.path();
-
-        if entry_path.is_dir() {
-            let (sub_size, sub_count) = Box::pin(get_dir_stats_async(&entry_path)).await?;
-            size += sub_size;
-            count += sub_count;
-        } else if entry_path.is_file() {
-            let metadata = entry.metadata().await.map_err(|e| e.to_string())?;
-            size += metadata.len();
-            count += 1;
-        }
-    }
-
-    Ok((size, count))
-}
-
 pub async fn fetch_categories_async(root_folder_path: PathBuf) -> Result<Vec<Category>, String> {
-    let mut read_dir = fs::read_dir(&root_folder_path)
-        .await
-        .map_err(|e| format!("Failed to read directory: {}", e))?;
-
-    let mut entry_vec = Vec::new();
-
-    while let Some(entry) = read_dir.next_entry().await.map_err(|e| e.to_string())? {
-        if entry
-            .file_type()
-            .await
-            .map(|ft| ft.is_dir() && entry.file_name() != "temp")
-            .unwrap_or(false)
-        {
-            entry_vec.push(entry);
-        }
-    }
-
-    let categories = futures::future::join_all(entry_vec.into_iter().map(|entry| {
-        let dir_name = entry.file_name().to_string_lossy().to_string();
-        let dir_path = root_folder_path.join(&dir_name);
-
-        task::spawn(async move {
-            match get_dir_stats_async(&dir_path).await {
-                Ok((size, count)) => Some(Category { name: dir_name, file_count: count, size }),
-                Err(e) => {
-                    log_error!("Error calculating stats for {:?}: {}", dir_path, e);
-                    None
-                }
-            }
-        })
-    }))
+    task::spawn_blocking(move || {
+        let names = list_category_dirs(&root_folder_path)
+            .map_err(|e| format!("Failed to read directory: {}", e))?;
+
+        let cache = get_or_init_cache(get_main_path().map_err(|e| e.to_string())?.join("cache"))
+            .map_err(|e| format!("Failed to get cache: {}", e))?;
+        let totals = cache
+            .category_totals(&root_folder_path)
+            .map_err(|e| format!("Failed to read category totals: {}", e))?;
+
+        Ok(names
+            .into_iter()
+            .map(|name| {
+                let (file_count, size) = totals.get(&name).copied().unwrap_or((0, 0));
+                Category { name, file_count, size }
+            })
+            .collect())
+    })
     .await
-    .into_iter()
-    .filter_map(|result| result.ok().flatten())
-    .collect();
-
-    Ok(categories)
+    .map_err(|e| format!("Category task failed: {}", e))?
 }
 
 #[tauri::command]
@@ -123,8 +81,22 @@ fn update_category_in_db(old_name: &str, new_name: &str) -> Result<(), String> {
         .transaction()
         .map_err(|e| format!("Failed to start transaction: {}", e))?;
 
-    tx.execute("UPDATE images SET category = ?1 WHERE category = ?2", [new_name, old_name])
-        .map_err(|e| format!("Failed to update images table: {}", e))?;
+    let root_folder_path = get_config().folderPath;
+    let old_dir = root_folder_path.join(old_name).to_string_lossy().to_string();
+    let new_dir = root_folder_path.join(new_name).to_string_lossy().to_string();
+
+    tx.execute(
+        "UPDATE images SET category = ?1, relative_path = ?3
+         WHERE category = ?2 AND relative_path = ?4",
+        [new_name, old_name, new_dir.as_str(), old_dir.as_str()],
+    )
+    .map_err(|e| format!("Failed to update images table: {}", e))?;
+
+    tx.execute(
+        "UPDATE library_dirs SET path = ?1 WHERE path = ?2",
+        [new_dir.as_str(), old_dir.as_str()],
+    )
+    .map_err(|e| format!("Failed to update library_dirs table: {}", e))?;
 
     tx.execute(
         "UPDATE tags SET name = ?1 WHERE name = ?2 AND is_category = 1",
@@ -0,0 +1,308 @@
+use crate::modules::mediatype::{DecodeTarget, DecodedImage, MediaFormat};
+use crate::modules::raw;
+use image::{DynamicImage, Rgba, RgbaImage};
+use std::path::Path;
+
+// Decoders for formats the `image` crate doesn't read: HEIC (libheif),
+// JPEG XL (jxl-oxide), SVG (resvg) and the flattened composite stored in
+// PSD files. AVIF and QOI go through `image` itself (see
+// `MediaFormat::image_format`). Every format is a cargo feature; without
+// it files are still recognised, listed as `unsupported`, and shown as a
+// fallback icon instead of a thumbnail. Camera RAW previews (see `raw`)
+// need no extra dependencies and are always built in.
+
+// Largest side an SVG is rasterised at when no target size is given.
+#[cfg(feature = "svg")]
+const SVG_MAX_SIDE: f32 = 2048.0;
+
+/// Whether this build has a decoder here for `format`.
+pub fn supports(format: MediaFormat) -> bool {
+    match format {
+        MediaFormat::Heic => cfg!(feature = "heic"),
+        MediaFormat::Jxl => cfg!(feature = "jxl"),
+        MediaFormat::Svg => cfg!(feature = "svg"),
+        MediaFormat::Psd => cfg!(feature = "psd"),
+        MediaFormat::Raw => true,
+        _ => false,
+    }
+}
+
+/// Decodes `path` as `format`, upright. Vector formats are rasterised for
+/// `target` when given, at their intrinsic size otherwise.
+pub fn decode(
+    path: &Path,
+    format: MediaFormat,
+    target: Option<DecodeTarget>,
+) -> Result<DecodedImage, Box<dyn std::error::Error + Send + Sync>> {
+    match format {
+        #[cfg(feature = "heic")]
+        MediaFormat::Heic => heic::decode(path),
+        #[cfg(feature = "jxl")]
+        MediaFormat::Jxl => jxl::decode(path),
+        #[cfg(feature = "svg")]
+        MediaFormat::Svg => svg::decode(path, target),
+        #[cfg(feature = "psd")]
+        MediaFormat::Psd => psd::decode(path),
+        MediaFormat::Raw => raw::decode(path, target),
+        _ => Err(format!("No {} decoder in this build: {}", format.label(), path.display()).into()),
+    }
+}
+
+/// Pixel size in display orientation, without decoding where the format allows.
+pub fn dimensions(
+    path: &Path,
+    format: MediaFormat,
+) -> Result<(u32, u32), Box<dyn std::error::Error + Send + Sync>> {
+    match format {
+        #[cfg(feature = "heic")]
+        MediaFormat::Heic => heic::dimensions(path),
+        #[cfg(feature = "jxl")]
+        MediaFormat::Jxl => jxl::dimensions(path),
+        #[cfg(feature = "svg")]
+        MediaFormat::Svg => svg::dimensions(path),
+        #[cfg(feature = "psd")]
+        MediaFormat::Psd => psd::dimensions(path),
+        MediaFormat::Raw => raw::dimensions(path),
+        _ => Err(format!("No {} decoder in this build: {}", format.label(), path.display()).into()),
+    }
+}
+
+#[cfg(feature = "heic")]
+mod heic {
+    use super::*;
+    use crate::modules::mediatype::DecodeSource;
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+    // libheif applies the rotation and mirroring boxes while decoding.
+    pub fn decode(path: &Path) -> Result<DecodedImage, Box<dyn std::error::Error + Send + Sync>> {
+        let context = HeifContext::read_from_file(path.to_str().ok_or("Path is not valid UTF-8")?)?;
+        let handle = context.primary_image_handle()?;
+        let icc_profile = handle.color_profile_raw().map(|profile| profile.data);
+        let alpha = handle.has_alpha_channel();
+        let chroma = if alpha { RgbChroma::Rgba } else { RgbChroma::Rgb };
+        let decoded = LibHeif::new().decode(&handle, ColorSpace::Rgb(chroma), None)?;
+
+        let plane = decoded.planes().interleaved.ok_or("HEIC image has no interleaved plane")?;
+        let row_len = plane.width as usize * if alpha { 4 } else { 3 };
+        let mut pixels = Vec::with_capacity(row_len * plane.height as usize);
+        for row in plane.data.chunks(plane.stride).take(plane.height as usize) {
+            pixels.extend_from_slice(&row[..row_len]);
+        }
+
+        let image = if alpha {
+            RgbaImage::from_raw(plane.width, plane.height, pixels).map(DynamicImage::ImageRgba8)
+        } else {
+            image::RgbImage::from_raw(plane.width, plane.height, pixels).map(DynamicImage::ImageRgb8)
+        };
+        let image = image.ok_or("Decoded HEIC has the wrong buffer size")?;
+        Ok(DecodedImage { image, icc_profile, source: DecodeSource::Full })
+    }
+
+    pub fn dimensions(path: &Path) -> Result<(u32, u32), Box<dyn std::error::Error + Send + Sync>> {
+        let context = HeifContext::read_from_file(path.to_str().ok_or("Path is not valid UTF-8")?)?;
+        let handle = context.primary_image_handle()?;
+        Ok((handle.width(), handle.height()))
+    }
+}
+
+#[cfg(feature = "jxl")]
+mod jxl {
+    use super::*;
+    use crate::modules::mediatype::decode_with;
+    use image::ImageDecoder;
+    use jxl_oxide::integration::JxlDecoder;
+    use std::fs::File;
+    use std::io::BufReader;
+
+    fn open(path: &Path) -> Result<JxlDecoder<BufReader<File>>, Box<dyn std::error::Error + Send + Sync>> {
+        JxlDecoder::new(BufReader::new(File::open(path)?)).map_err(|e| e.to_string().into())
+    }
+
+    pub fn decode(path: &Path) -> Result<DecodedImage, Box<dyn std::error::Error + Send + Sync>> {
+        decode_with(open(path)?)
+    }
+
+    pub fn dimensions(path: &Path) -> Result<(u32, u32), Box<dyn std::error::Error + Send + Sync>> {
+        let mut decoder = open(path)?;
+        let (w, h) = decoder.dimensions();
+        let transposed = decoder.orientation().is_ok_and(|o| {
+            use image::metadata::Orientation::*;
+            matches!(o, Rotate90 | Rotate270 | Rotate90FlipH | Rotate270FlipH)
+        });
+        Ok(if transposed { (h, w) } else { (w, h) })
+    }
+}
+
+#[cfg(feature = "svg")]
+mod svg {
+    use super::*;
+    use crate::modules::mediatype::DecodeSource;
+    use resvg::{tiny_skia, usvg};
+
+    fn parse(path: &Path) -> Result<usvg::Tree, Box<dyn std::error::Error + Send + Sync>> {
+        let data = std::fs::read(path)?;
+        Ok(usvg::Tree::from_data(&data, &usvg::Options::default())?)
+    }
+
+    pub fn decode(
+        path: &Path,
+        target: Option<DecodeTarget>,
+    ) -> Result<DecodedImage, Box<dyn std::error::Error + Send + Sync>> {
+        let tree = parse(path)?;
+        let (w, h) = (tree.size().width(), tree.size().height());
+        let scale = match target {
+            Some(t) if t.cover => (t.width as f32 / w).max(t.height as f32 / h),
+            Some(t) => (t.width as f32 / w).min(t.height as f32 / h),
+            None => 1.0,
+        }
+        .min(SVG_MAX_SIDE / w.max(h));
+
+        let (pw, ph) = ((w * scale).ceil().max(1.0) as u32, (h * scale).ceil().max(1.0) as u32);
+        let mut pixmap = tiny_skia::Pixmap::new(pw, ph).ok_or("SVG has no drawable area")?;
+        resvg::render(&tree, tiny_skia::Transform::from_scale(scale, scale), &mut pixmap.as_mut());
+
+        // tiny-skia works in premultiplied alpha
+        let mut image = RgbaImage::new(pw, ph);
+        for (dst, src) in image.pixels_mut().zip(pixmap.pixels()) {
+            let c = src.demultiply();
+            *dst = Rgba([c.red(), c.green(), c.blue(), c.alpha()]);
+        }
+        Ok(DecodedImage { image: DynamicImage::ImageRgba8(image), icc_profile: None, source: DecodeSource::Full })
+    }
+
+    pub fn dimensions(path: &Path) -> Result<(u32, u32), Box<dyn std::error::Error + Send + Sync>> {
+        let size = parse(path)?.size().to_int_size();
+        Ok((size.width(), size.height()))
+    }
+}
+
+#[cfg(feature = "psd")]
+mod psd {
+    use super::*;
+    use crate::modules::mediatype::DecodeSource;
+    use std::fs::File;
+    use std::io::Read;
+
+    // Only the merged composite is read; layers are never composited here.
+    pub fn decode(path: &Path) -> Result<DecodedImage, Box<dyn std::error::Error + Send + Sync>> {
+        let bytes = std::fs::read(path)?;
+        let psd = ::psd::Psd::from_bytes(&bytes).map_err(|e| e.to_string())?;
+        let image = RgbaImage::from_raw(psd.width(), psd.height(), psd.rgba())
+            .ok_or("PSD composite has the wrong buffer size")?;
+        Ok(DecodedImage { image: DynamicImage::ImageRgba8(image), icc_profile: None, source: DecodeSource::Full })
+    }
+
+    // "8BPS", version, 6 reserved bytes and the channel count precede the
+    // big-endian height and width.
+    pub fn dimensions(path: &Path) -> Result<(u32, u32), Box<dyn std::error::Error + Send + Sync>> {
+        let mut header = [0u8; 22];
+        File::open(path)?.read_exact(&mut header)?;
+        let height = u32::from_be_bytes(header[14..18].try_into()?);
+        let width = u32::from_be_bytes(header[18..22].try_into()?);
+        Ok((width, height))
+    }
+}
+
+// ── fallback icons ────────────────────────────────────────────────────────────
+
+// 3×5 glyphs for the letters of every format label, one row per byte.
+const GLYPHS: &[(char, [u8; 5])] = &[
+    ('A', [0b010, 0b101, 0b111, 0b101, 0b101]),
+    ('B', [0b110, 0b101, 0b110, 0b101, 0b110]),
+    ('C', [0b011, 0b100, 0b100, 0b100, 0b011]),
+    ('D', [0b110, 0b101, 0b101, 0b101, 0b110]),
+    ('E', [0b111, 0b100, 0b110, 0b100, 0b111]),
+    ('F', [0b111, 0b100, 0b110, 0b100, 0b100]),
+    ('G', [0b011, 0b100, 0b101, 0b101, 0b011]),
+    ('H', [0b101, 0b101, 0b111, 0b101, 0b101]),
+    ('I', [0b111, 0b010, 0b010, 0b010, 0b111]),
+    ('J', [0b001, 0b001, 0b001, 0b101, 0b010]),
+    ('L', [0b100, 0b100, 0b100, 0b100, 0b111]),
+    ('M', [0b101, 0b111, 0b111, 0b101, 0b101]),
+    ('N', [0b110, 0b101, 0b101, 0b101, 0b101]),
+    ('O', [0b010, 0b101, 0b101, 0b101, 0b010]),
+    ('P', [0b110, 0b101, 0b110, 0b100, 0b100]),
+    ('Q', [0b010, 0b101, 0b101, 0b110, 0b011]),
+    ('S', [0b011, 0b100, 0b010, 0b001, 0b110]),
+    ('T', [0b111, 0b010, 0b010, 0b010, 0b010]),
+    ('V', [0b101, 0b101, 0b101, 0b101, 0b010]),
+    ('W', [0b101, 0b101, 0b111, 0b111, 0b101]),
+    ('X', [0b101, 0b101, 0b010, 0b101, 0b101]),
+];
+
+const PAGE: Rgba<u8> = Rgba([238, 240, 243, 255]);
+const PAGE_EDGE: Rgba<u8> = Rgba([196, 200, 207, 255]);
+const LABEL_TEXT: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+fn accent(format: MediaFormat) -> Rgba<u8> {
+    match format {
+        MediaFormat::Avif => Rgba([232, 120, 40, 255]),
+        MediaFormat::Heic => Rgba([52, 120, 246, 255]),
+        MediaFormat::Jxl => Rgba([20, 150, 140, 255]),
+        MediaFormat::Svg => Rgba([240, 170, 30, 255]),
+        MediaFormat::Qoi => Rgba([140, 90, 210, 255]),
+        MediaFormat::Psd => Rgba([49, 168, 255, 255]),
+        _ => Rgba([110, 118, 130, 255]),
+    }
+}
+
+/// A `side`×`side` document icon with the format's name on a coloured band,
+/// drawn for files this build can't decode. Transparent around the page.
+pub fn fallback_icon(format: MediaFormat, side: u32) -> DynamicImage {
+    let side = side.max(16);
+    let mut icon = RgbaImage::new(side, side);
+    let s = side as f32;
+
+    let (left, right) = ((s * 0.2) as u32, (s * 0.8) as u32);
+    let (top, bottom) = ((s * 0.1) as u32, (s * 0.9) as u32);
+    let fold = ((right - left) as f32 * 0.28) as u32;
+    let edge = (side / 64).max(1);
+
+    // Page with its top-right corner folded over
+    for y in top..bottom {
+        for x in left..right {
+            let (dx, dy) = (right - 1 - x, y - top);
+            if dx + dy < fold {
+                continue;
+            }
+            let on_edge = x < left + edge || x >= right - edge || y < top + edge || y >= bottom - edge;
+            let on_flap = dx < fold && dy < fold;
+            icon.put_pixel(x, y, if on_edge || on_flap { PAGE_EDGE } else { PAGE });
+        }
+    }
+
+    // Label band across the lower part of the page
+    let band_top = top + (bottom - top) * 55 / 100;
+    let band_bottom = top + (bottom - top) * 80 / 100;
+    let color = accent(format);
+    for y in band_top..band_bottom {
+        for x in left..right {
+            icon.put_pixel(x, y, color);
+        }
+    }
+
+    let label = format.label();
+    let cols = label.chars().count() as u32 * 4 - 1;
+    let scale = ((right - left) * 8 / 10 / cols).min((band_bottom - band_top) * 6 / 10 / 5).max(1);
+    let text_x = left + ((right - left).saturating_sub(cols * scale)) / 2;
+    let text_y = band_top + ((band_bottom - band_top).saturating_sub(5 * scale)) / 2;
+    for (i, ch) in label.chars().enumerate() {
+        let Some((_, rows)) = GLYPHS.iter().find(|(c, _)| *c == ch) else { continue };
+        for (row, bits) in rows.iter().enumerate() {
+            for col in 0..3u32 {
+                if bits & (0b100 >> col) == 0 {
+                    continue;
+                }
+                let x0 = text_x + (i as u32 * 4 + col) * scale;
+                let y0 = text_y + row as u32 * scale;
+                for y in y0..(y0 + scale).min(side) {
+                    for x in x0..(x0 + scale).min(side) {
+                        icon.put_pixel(x, y, LABEL_TEXT);
+                    }
+                }
+            }
+        }
+    }
+
+    DynamicImage::ImageRgba8(icon)
+}
@@ -1,10 +1,14 @@
 use crate::modules::filehandler::initialize_cache;
+use crate::modules::imgoptimize::{invalidate_profile, set_memory_budget_mb};
 use crate::modules::logger::LOGGER;
 use crate::modules::pathutils::get_main_path;
+use crate::modules::thumbcache::{self, Background, FitMode, ThumbFormat};
+use crate::modules::watcher::watch_root;
 use crate::{log_error, log_info, log_pre};
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::collections::BTreeMap;
 use std::fs;
 use std::io::{self};
 use std::path::{Path, PathBuf};
@@ -22,6 +26,65 @@ pub struct Config {
     pub sensitive_tags: Vec<String>,
     #[serde(default)]
     pub owner_name: String,
+    #[serde(default = "default_thumb_cache_mb")]
+    pub thumb_cache_mb: u64,
+    #[serde(default = "default_thumb_memory_mb")]
+    pub thumb_memory_mb: u64,
+    // Empty keeps alpha; otherwise "checkerboard" or a hex colour
+    #[serde(default)]
+    pub thumb_background: String,
+    #[serde(default = "default_thumb_profiles")]
+    pub thumb_profiles: BTreeMap<String, ThumbProfile>,
+}
+
+/// Named thumbnail settings the frontend asks for by name (`grid`,
+/// `preview`, ...) instead of repeating sizes at every call site.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ThumbProfile {
+    pub width: u32,
+    pub height: u32,
+    pub quality: u8,
+    // "auto", "jpeg", "webp", "webp-lossless" or "png"; empty means auto
+    #[serde(default)]
+    pub format: String,
+    // "contain", "cover", "square" or "smart"; empty means contain
+    #[serde(default)]
+    pub fit: String,
+}
+
+impl ThumbProfile {
+    fn new(width: u32, height: u32, quality: u8, fit: &str) -> ThumbProfile {
+        ThumbProfile { width, height, quality, format: "auto".to_string(), fit: fit.to_string() }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.width == 0 || self.height == 0 {
+            return Err("Thumbnail profile size must be at least 1x1".to_string());
+        }
+        if !(1..=100).contains(&self.quality) {
+            return Err(format!("Thumbnail profile quality must be 1-100, got {}", self.quality));
+        }
+        ThumbFormat::parse(&self.format).ok_or_else(|| format!("Unknown thumbnail format: {}", self.format))?;
+        FitMode::parse(&self.fit).ok_or_else(|| format!("Unknown fit mode: {}", self.fit))?;
+        Ok(())
+    }
+}
+
+fn default_thumb_profiles() -> BTreeMap<String, ThumbProfile> {
+    BTreeMap::from([
+        ("grid".to_string(), ThumbProfile::new(320, 320, 80, "cover")),
+        ("preview".to_string(), ThumbProfile::new(960, 540, 75, "contain")),
+        ("icon".to_string(), ThumbProfile::new(64, 64, 75, "square")),
+        ("fullscreen".to_string(), ThumbProfile::new(2560, 1440, 90, "contain")),
+    ])
+}
+
+fn default_thumb_cache_mb() -> u64 {
+    1024
+}
+
+fn default_thumb_memory_mb() -> u64 {
+    256
 }
 
 pub static CONFIG: Lazy<RwLock<Config>> = Lazy::new(|| {
@@ -118,6 +181,10 @@ impl Default for Config {
             batch_process: 32,
             sensitive_tags: vec!["explicit".to_string()],
             owner_name: String::new(),
+            thumb_cache_mb: default_thumb_cache_mb(),
+            thumb_memory_mb: default_thumb_memory_mb(),
+            thumb_background: String::new(),
+            thumb_profiles: default_thumb_profiles(),
         }
     }
 }
@@ -186,7 +253,9 @@ pub async fn update_settings(new_settings: Value) -> Result<Config, String> {
         format!("Failed to read config: {}", e)
     })?;
 
+    let mut root_changed = false;
     if let Some(folder_path) = new_settings.get("folderPath").and_then(|v| v.as_str()) {
+        root_changed = current_config.folderPath != PathBuf::from(folder_path);
         current_config.folderPath = PathBuf::from(folder_path);
         if let Err(e) = current_config.ensure_uncategorized_dir() {
             log_error!("Failed to create uncategorized directory: {}", e);
@@ -220,6 +289,40 @@ pub async fn update_settings(new_settings: Value) -> Result<Config, String> {
     if let Some(v) = new_settings.get("owner_name").and_then(|v| v.as_str()) {
         current_config.owner_name = v.to_string();
     }
+    let mut thumb_budget_changed = false;
+    if let Some(v) = new_settings.get("thumb_cache_mb").and_then(|v| v.as_u64()) {
+        thumb_budget_changed = current_config.thumb_cache_mb != v;
+        current_config.thumb_cache_mb = v;
+    }
+    if let Some(v) = new_settings.get("thumb_background").and_then(|v| v.as_str()) {
+        if Background::parse(v).is_none() {
+            return Err(format!("Invalid thumbnail background: {}", v));
+        }
+        current_config.thumb_background = v.to_string();
+    }
+    let mut memory_budget_changed = false;
+    if let Some(v) = new_settings.get("thumb_memory_mb").and_then(|v| v.as_u64()) {
+        memory_budget_changed = current_config.thumb_memory_mb != v;
+        current_config.thumb_memory_mb = v;
+    }
+    // Entries replace the profile of the same name; `null` removes it
+    let mut stale_profiles = Vec::new();
+    if let Some(profiles) = new_settings.get("thumb_profiles").and_then(|v| v.as_object()) {
+        for (name, value) in profiles {
+            let old = if value.is_null() {
+                current_config.thumb_profiles.remove(name)
+            } else {
+                let profile: ThumbProfile = serde_json::from_value(value.clone())
+                    .map_err(|e| format!("Invalid thumbnail profile {}: {}", name, e))?;
+                profile.validate().map_err(|e| format!("Invalid thumbnail profile {}: {}", name, e))?;
+                current_config
+                    .thumb_profiles
+                    .insert(name.clone(), profile.clone())
+                    .filter(|old| *old != profile)
+            };
+            stale_profiles.extend(old);
+        }
+    }
 
     current_config.write_config(&config_path).map_err(|e| {
         log_error!("Failed to write config: {}", e);
@@ -231,5 +334,20 @@ pub async fn update_settings(new_settings: Value) -> Result<Config, String> {
         format!("Failed to refresh global config: {}", e)
     })?;
 
+    if root_changed {
+        if let Err(e) = watch_root(current_config.folderPath.clone()) {
+            log_error!("Failed to watch new root folder: {}", e);
+        }
+    }
+    if thumb_budget_changed {
+        thumbcache::set_budget_mb(current_config.thumb_cache_mb);
+    }
+    if memory_budget_changed {
+        set_memory_budget_mb(current_config.thumb_memory_mb);
+    }
+    for profile in &stale_profiles {
+        invalidate_profile(profile);
+    }
+
     Ok(current_config)
 }
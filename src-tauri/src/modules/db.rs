@@ -1,12 +1,12 @@
 use crate::modules::config::get_config;
 use crate::modules::pathutils::get_main_path;
 use crate::{log_error, log_info};
-use rusqlite::{Connection, Result, ToSql};
+use rusqlite::{Connection, OptionalExtension, Result, ToSql};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::fs::create_dir_all;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Image {
@@ -46,12 +46,42 @@ pub fn init_db() -> Result<Connection, String> {
             category TEXT NOT NULL,
             filename TEXT NOT NULL,
             created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            rating INTEGER NOT NULL DEFAULT 0,
+            size INTEGER,
+            modified_ms INTEGER,
+            created_ms INTEGER,
+            hash TEXT,
+            media_kind TEXT,
+            placeholder TEXT,
+            width INTEGER,
+            height INTEGER,
+            frame_count INTEGER,
+            duration_ms INTEGER,
+            camera_make TEXT,
+            camera_model TEXT,
+            lens_model TEXT,
+            taken_at TEXT,
+            iso INTEGER,
+            exposure_time TEXT,
+            f_number REAL,
+            focal_length REAL,
+            phash INTEGER,
+            dupe_checked_ms INTEGER,
             UNIQUE(relative_path, filename)
         )",
         [],
     )
     .map_err(|e| e.to_string())?;
 
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS library_dirs (
+            path TEXT PRIMARY KEY,
+            modified_ms INTEGER NOT NULL
+        )",
+        [],
+    )
+    .map_err(|e| e.to_string())?;
+
     conn.execute(
         "CREATE TABLE IF NOT EXISTS tags (
             id INTEGER PRIMARY KEY,
@@ -262,6 +292,31 @@ pub fn get_image_id(path: PathBuf, category: String) -> Result<i64, String> {
         .map_err(|e| e.to_string())
 }
 
+pub fn get_image_path_by_id(image_id: i64) -> Result<Option<PathBuf>, String> {
+    let conn = connect_db()?;
+    conn.query_row(
+        "SELECT relative_path, filename FROM images WHERE id = ?1",
+        [image_id],
+        image_path_from_row,
+    )
+    .optional()
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn set_image_rating(image_id: i64, rating: u8) -> Result<(), String> {
+    if rating > 5 {
+        return Err(format!("Rating must be between 0 and 5, got {}", rating));
+    }
+    let conn = connect_db()?;
+    conn.execute(
+        "UPDATE images SET rating = ?1 WHERE id = ?2",
+        [rating as i64, image_id],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
 #[tauri::command]
 pub fn remove_tag(name: String) -> Result<(), String> {
     let mut conn = connect_db()?;
@@ -455,6 +510,125 @@ pub fn get_batch_image_tags(
     Ok(result)
 }
 
+/// Deletes the image row for `path`; its tag links go with it via CASCADE.
+pub fn remove_image_by_path(path: &Path) -> Result<(), String> {
+    let conn = connect_db()?;
+    let (relative_path, filename) = split_image_path(path)?;
+    conn.execute(
+        "DELETE FROM images WHERE relative_path = ?1 AND filename = ?2",
+        [relative_path, filename],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Re-points the image row at `old_path` to `new_path`, keeping its id and tags.
+pub fn move_image_path(old_path: &Path, new_path: &Path, new_category: &str) -> Result<(), String> {
+    let conn = connect_db()?;
+    let (old_relative, old_filename) = split_image_path(old_path)?;
+    let (new_relative, new_filename) = split_image_path(new_path)?;
+    conn.execute(
+        "UPDATE OR IGNORE images SET relative_path = ?1, filename = ?2, category = ?3
+         WHERE relative_path = ?4 AND filename = ?5",
+        [new_relative, new_filename, new_category, old_relative, old_filename],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+fn split_image_path(path: &Path) -> Result<(&str, &str), String> {
+    let filename = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or("Invalid filename")?;
+
+    let relative_path = path
+        .parent()
+        .and_then(|p| p.to_str())
+        .ok_or("Invalid path")?;
+
+    Ok((relative_path, filename))
+}
+
+fn image_path_from_row(row: &rusqlite::Row) -> rusqlite::Result<PathBuf> {
+    let relative_path: String = row.get(0)?;
+    let filename: String = row.get(1)?;
+    Ok(PathBuf::from(relative_path).join(filename))
+}
+
+/// Full paths of images carrying every tag in `tags`.
+pub fn get_image_paths_with_all_tags(tags: &[String]) -> Result<HashSet<PathBuf>, String> {
+    let conn = connect_db()?;
+    let placeholders = vec!["?"; tags.len()].join(",");
+    let query = format!(
+        "SELECT i.relative_path, i.filename FROM images i
+         JOIN image_tags it ON i.id = it.image_id
+         JOIN tags t ON it.tag_id = t.id
+         WHERE t.name IN ({})
+         GROUP BY i.id
+         HAVING COUNT(DISTINCT t.name) = ?",
+        placeholders
+    );
+
+    let mut params: Vec<Box<dyn ToSql>> = tags
+        .iter()
+        .map(|s| Box::new(s.clone()) as Box<dyn ToSql>)
+        .collect();
+    params.push(Box::new(tags.len() as i64));
+
+    let mut stmt = conn.prepare(&query).map_err(|e| e.to_string())?;
+    let paths = stmt
+        .query_map(
+            rusqlite::params_from_iter(params.iter().map(|p| &**p)),
+            image_path_from_row,
+        )
+        .map_err(|e| e.to_string())?
+        .collect::<Result<HashSet<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(paths)
+}
+
+/// Full paths of images carrying at least one tag in `tags`.
+pub fn get_image_paths_with_any_tag(tags: &[String]) -> Result<HashSet<PathBuf>, String> {
+    let conn = connect_db()?;
+    let placeholders = vec!["?"; tags.len()].join(",");
+    let query = format!(
+        "SELECT DISTINCT i.relative_path, i.filename FROM images i
+         JOIN image_tags it ON i.id = it.image_id
+         JOIN tags t ON it.tag_id = t.id
+         WHERE t.name IN ({})",
+        placeholders
+    );
+
+    let mut stmt = conn.prepare(&query).map_err(|e| e.to_string())?;
+    let paths = stmt
+        .query_map(rusqlite::params_from_iter(tags.iter()), image_path_from_row)
+        .map_err(|e| e.to_string())?
+        .collect::<Result<HashSet<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(paths)
+}
+
+/// Full paths of images that have at least one non-category tag.
+pub fn get_tagged_image_paths() -> Result<HashSet<PathBuf>, String> {
+    let conn = connect_db()?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT DISTINCT i.relative_path, i.filename FROM images i
+             JOIN image_tags it ON i.id = it.image_id
+             JOIN tags t ON it.tag_id = t.id
+             WHERE t.is_category = 0",
+        )
+        .map_err(|e| e.to_string())?;
+
+    let paths = stmt
+        .query_map([], image_path_from_row)
+        .map_err(|e| e.to_string())?
+        .collect::<Result<HashSet<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(paths)
+}
+
 pub fn migrate_database() -> Result<(), String> {
     let conn = connect_db()?;
 
@@ -476,5 +650,50 @@ pub fn migrate_database() -> Result<(), String> {
         log_info!("Added is_category column to tags table");
     }
 
+    add_column_if_missing(&conn, "images", "rating", "INTEGER NOT NULL DEFAULT 0")?;
+    add_column_if_missing(&conn, "images", "size", "INTEGER")?;
+    add_column_if_missing(&conn, "images", "modified_ms", "INTEGER")?;
+    add_column_if_missing(&conn, "images", "created_ms", "INTEGER")?;
+    add_column_if_missing(&conn, "images", "hash", "TEXT")?;
+    add_column_if_missing(&conn, "images", "media_kind", "TEXT")?;
+    add_column_if_missing(&conn, "images", "placeholder", "TEXT")?;
+    add_column_if_missing(&conn, "images", "width", "INTEGER")?;
+    add_column_if_missing(&conn, "images", "height", "INTEGER")?;
+    add_column_if_missing(&conn, "images", "frame_count", "INTEGER")?;
+    add_column_if_missing(&conn, "images", "duration_ms", "INTEGER")?;
+    add_column_if_missing(&conn, "images", "camera_make", "TEXT")?;
+    add_column_if_missing(&conn, "images", "camera_model", "TEXT")?;
+    add_column_if_missing(&conn, "images", "lens_model", "TEXT")?;
+    add_column_if_missing(&conn, "images", "taken_at", "TEXT")?;
+    add_column_if_missing(&conn, "images", "iso", "INTEGER")?;
+    add_column_if_missing(&conn, "images", "exposure_time", "TEXT")?;
+    add_column_if_missing(&conn, "images", "f_number", "REAL")?;
+    add_column_if_missing(&conn, "images", "focal_length", "REAL")?;
+    add_column_if_missing(&conn, "images", "phash", "INTEGER")?;
+    add_column_if_missing(&conn, "images", "dupe_checked_ms", "INTEGER")?;
+
+    conn.execute_batch(
+        "CREATE INDEX IF NOT EXISTS idx_images_category ON images(category);
+         CREATE INDEX IF NOT EXISTS idx_images_hash ON images(hash);",
+    )
+    .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+fn add_column_if_missing(conn: &Connection, table: &str, column: &str, decl: &str) -> Result<(), String> {
+    let columns = conn
+        .prepare(&format!("PRAGMA table_info({})", table))
+        .map_err(|e| e.to_string())?
+        .query_map([], |row| Ok(row.get::<_, String>(1)?))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<String>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    if !columns.iter().any(|c| c == column) {
+        conn.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl), [])
+            .map_err(|e| e.to_string())?;
+        log_info!("Added {} column to {} table", column, table);
+    }
     Ok(())
 }
@@ -0,0 +1,232 @@
+use crate::modules::imgoptimize::{encode, resize_exact};
+use crate::modules::mediatype::{self, DecodedImage, MediaKind};
+use crate::modules::thumbcache::{self, ThumbFormat, ThumbKey};
+use crate::log_info;
+use image::DynamicImage;
+use once_cell::sync::Lazy;
+use rayon::prelude::*;
+use std::path::Path;
+use std::sync::{Mutex, PoisonError};
+use std::time::Instant;
+
+// Deep-zoom pyramids let the viewer pan and zoom scans far larger than the
+// screen without shipping the original to the webview. They follow the DZI
+// layout that OpenSeadragon and similar viewers read:
+//
+//     lockerz://dzi/<image id>.dzi                               descriptor
+//     lockerz://dzi/<image id>_files/<level>/<col>_<row>.jpg      tiles
+//
+// Level `n` fits the image into 2^n pixels on its long side, so the top
+// level is the image itself and each level below halves it. Tiles are
+// TILE_SIZE square plus TILE_OVERLAP pixels shared with each neighbour.
+//
+// Any tile of the upper levels needs the whole image decoded, so the first
+// missing tile builds the full pyramid in one pass, each level resized from
+// the one above it. Builds run one at a time, which bounds memory to one
+// decoded source and its half-size copy. Tiles go to the thumbnail disk
+// store under content-addressed keys, sharing its budget and LRU eviction;
+// a tile evicted from a pyramid that is still in use rebuilds it. A build
+// pins its own tiles so it can't evict them before it finishes, and images
+// whose pyramid would take more than MAX_BUDGET_SHARE of the budget are
+// refused, since they would push out the rest of the cache or themselves.
+//
+// The descriptor says `jpg`, but tiles with transparent pixels are WebP;
+// the protocol sends the type sniffed from the bytes, so viewers don't care.
+
+const TILE_SIZE: u32 = 254;
+const TILE_OVERLAP: u32 = 1;
+const TILE_QUALITY: u8 = 85;
+// Encoded size of a tile per pixel at TILE_QUALITY, erring high for photos
+const TILE_BYTES_PER_PIXEL: f64 = 0.35;
+const MAX_BUDGET_SHARE: f64 = 0.25;
+
+static BUILD_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+/// Level and tile geometry of a pyramid over a `width`×`height` image.
+#[derive(Debug, Clone, Copy)]
+struct Pyramid {
+    width: u32,
+    height: u32,
+    max_level: u32,
+}
+
+impl Pyramid {
+    fn new(width: u32, height: u32) -> Pyramid {
+        let long_side = width.max(height).max(1) as u64;
+        let mut max_level = 0;
+        while (1u64 << max_level) < long_side {
+            max_level += 1;
+        }
+        Pyramid { width, height, max_level }
+    }
+
+    fn level_size(&self, level: u32) -> (u32, u32) {
+        let scale = 1u64 << (self.max_level - level);
+        let shrink = |side: u32| (side as u64).div_ceil(scale).max(1) as u32;
+        (shrink(self.width), shrink(self.height))
+    }
+
+    fn tile_counts(&self, level: u32) -> (u32, u32) {
+        let (w, h) = self.level_size(level);
+        (w.div_ceil(TILE_SIZE), h.div_ceil(TILE_SIZE))
+    }
+
+    fn contains(&self, level: u32, col: u32, row: u32) -> bool {
+        if level > self.max_level {
+            return false;
+        }
+        let (cols, rows) = self.tile_counts(level);
+        col < cols && row < rows
+    }
+
+    // Rough disk size of all levels' tiles
+    fn estimated_bytes(&self) -> u64 {
+        let pixels: u64 = (0..=self.max_level)
+            .map(|level| {
+                let (w, h) = self.level_size(level);
+                w as u64 * h as u64
+            })
+            .sum();
+        (pixels as f64 * TILE_BYTES_PER_PIXEL) as u64
+    }
+
+    // Pixel rect of a tile within its level, overlap included
+    fn tile_rect(&self, level: u32, col: u32, row: u32) -> (u32, u32, u32, u32) {
+        let (w, h) = self.level_size(level);
+        let span = |index: u32, side: u32| {
+            let start = (index * TILE_SIZE).saturating_sub(if index > 0 { TILE_OVERLAP } else { 0 });
+            let end = ((index + 1) * TILE_SIZE + TILE_OVERLAP).min(side);
+            (start, end - start)
+        };
+        let (x, tile_w) = span(col, w);
+        let (y, tile_h) = span(row, h);
+        (x, y, tile_w, tile_h)
+    }
+}
+
+/// The DZI descriptor of `path`, with a tag that changes with its contents.
+pub fn descriptor(path: &Path) -> Result<(String, String), Box<dyn std::error::Error + Send + Sync>> {
+    let hash = zoomable_source(path)?;
+    let (width, height) = mediatype::dimensions(path)?;
+    check_budget(&Pyramid::new(width, height))?;
+    let xml = format!(
+        concat!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+            r#"<Image xmlns="http://schemas.microsoft.com/deepzoom/2008" TileSize="{}" Overlap="{}" Format="jpg">"#,
+            r#"<Size Width="{}" Height="{}"/></Image>"#
+        ),
+        TILE_SIZE, TILE_OVERLAP, width, height
+    );
+    Ok((format!("{}_dz{}", hash, TILE_SIZE), xml))
+}
+
+/// Key of one tile of `path`'s pyramid, or `None` if the pyramid has no
+/// such tile.
+pub fn tile_key(
+    path: &Path,
+    level: u32,
+    col: u32,
+    row: u32,
+) -> Result<Option<ThumbKey>, Box<dyn std::error::Error + Send + Sync>> {
+    let hash = zoomable_source(path)?;
+    let (width, height) = mediatype::dimensions(path)?;
+    let pyramid = Pyramid::new(width, height);
+    check_budget(&pyramid)?;
+    if !pyramid.contains(level, col, row) {
+        return Ok(None);
+    }
+    Ok(Some(ThumbKey::tile(&hash, TILE_SIZE, level, col, row)))
+}
+
+/// Reads a tile from the disk store, building the pyramid if it isn't there.
+pub fn load_tile(path: &Path, key: &ThumbKey) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(stored) = thumbcache::load(key) {
+        return Ok(stored);
+    }
+
+    let _guard = BUILD_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    // Another request may have built it while this one waited
+    if let Some(stored) = thumbcache::load(key) {
+        return Ok(stored);
+    }
+    build(path)?;
+    thumbcache::load(key).ok_or_else(|| format!("Tile {} was not built", key.as_str()).into())
+}
+
+fn zoomable_source(path: &Path) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    match thumbcache::source_of(path)? {
+        (hash, MediaKind::Image | MediaKind::AnimatedImage) => Ok(hash),
+        _ => Err(format!("Not a decodable image: {}", path.display()).into()),
+    }
+}
+
+fn check_budget(pyramid: &Pyramid) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let budget = thumbcache::stats().budget_bytes;
+    let estimate = pyramid.estimated_bytes();
+    if estimate as f64 > budget as f64 * MAX_BUDGET_SHARE {
+        return Err(format!(
+            "A {}x{} deep-zoom pyramid needs about {} MB, too much for a {} MB thumbnail cache",
+            pyramid.width,
+            pyramid.height,
+            estimate / (1024 * 1024),
+            budget / (1024 * 1024)
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn build(path: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let started = Instant::now();
+    let hash = zoomable_source(path)?;
+    let DecodedImage { image, icc_profile, .. } = mediatype::decode_large_image(path)?;
+    let pyramid = Pyramid::new(image.width(), image.height());
+    check_budget(&pyramid)?;
+    let _pinned = thumbcache::pin(format!("{}_dz{}_", hash, TILE_SIZE));
+
+    let mut level_image = if image.color().has_alpha() {
+        DynamicImage::ImageRgba8(image.into_rgba8())
+    } else {
+        DynamicImage::ImageRgb8(image.into_rgb8())
+    };
+
+    let mut tiles = 0;
+    for level in (0..=pyramid.max_level).rev() {
+        let (cols, rows) = pyramid.tile_counts(level);
+        (0..cols * rows).into_par_iter().try_for_each(|i| {
+            let (col, row) = (i % cols, i / cols);
+            let (x, y, w, h) = pyramid.tile_rect(level, col, row);
+
+            // Colour conversion per tile keeps the full-size source from
+            // being copied twice more
+            let mut tile = level_image.crop_imm(x, y, w, h);
+            if let Some(icc) = &icc_profile {
+                mediatype::to_srgb(&mut tile, icc);
+            }
+            let transparent = tile.as_rgba8().is_some_and(|t| t.pixels().any(|p| p[3] < u8::MAX));
+            if !transparent {
+                tile = DynamicImage::ImageRgb8(tile.into_rgb8());
+            }
+
+            let bytes = encode(&tile, ThumbFormat::Auto, transparent, TILE_QUALITY)?;
+            thumbcache::store(&ThumbKey::tile(&hash, TILE_SIZE, level, col, row), &bytes);
+            Ok::<_, Box<dyn std::error::Error + Send + Sync>>(())
+        })?;
+        tiles += cols * rows;
+
+        if level > 0 {
+            let (w, h) = pyramid.level_size(level - 1);
+            level_image = resize_exact(level_image, w, h)?;
+        }
+    }
+
+    log_info!(
+        "Built a {}x{} deep-zoom pyramid ({} levels, {} tiles) in {} ms",
+        pyramid.width,
+        pyramid.height,
+        pyramid.max_level + 1,
+        tiles,
+        started.elapsed().as_millis()
+    );
+    Ok(())
+}
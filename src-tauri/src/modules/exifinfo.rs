@@ -0,0 +1,123 @@
+use crate::modules::db::connect_db;
+use exif::{DateTime, Exif, In, Rational, Tag, Value};
+use rusqlite::{params, OptionalExtension};
+use serde::Serialize;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+// The shooting details worth showing and filtering by, read from the EXIF
+// of JPEG, TIFF, HEIF, PNG, WebP and camera RAW files. RAWs are read from
+// their own IFDs, since the embedded previews usually have no EXIF. The
+// index pass stores them in the `images` table; an empty `camera_make`
+// marks a file that was read but had nothing.
+
+#[derive(Debug, Serialize, Clone, Default, PartialEq)]
+pub struct ExifInfo {
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub lens: Option<String>,
+    /// Local time the picture was taken, as `YYYY-MM-DDTHH:MM:SS`.
+    pub taken_at: Option<String>,
+    pub iso: Option<u32>,
+    /// Shutter speed as photographers write it: `1/250`, `2.5`.
+    pub exposure_time: Option<String>,
+    pub f_number: Option<f64>,
+    /// In millimetres, as recorded (not 35 mm equivalent).
+    pub focal_length: Option<f64>,
+}
+
+impl ExifInfo {
+    pub fn is_empty(&self) -> bool {
+        *self == ExifInfo::default()
+    }
+}
+
+/// Reads the EXIF summary of a file; empty when it has none.
+pub fn read(path: &Path) -> Result<ExifInfo, Box<dyn std::error::Error + Send + Sync>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let exif = match exif::Reader::new().read_from_container(&mut reader) {
+        Ok(exif) => exif,
+        Err(exif::Error::NotFound(_)) => return Ok(ExifInfo::default()),
+        Err(e) => return Err(e.to_string().into()),
+    };
+
+    Ok(ExifInfo {
+        make: ascii(&exif, Tag::Make),
+        model: ascii(&exif, Tag::Model),
+        lens: ascii(&exif, Tag::LensModel),
+        taken_at: ascii(&exif, Tag::DateTimeOriginal)
+            .or_else(|| ascii(&exif, Tag::DateTime))
+            .and_then(|text| DateTime::from_ascii(text.as_bytes()).ok())
+            .map(|t| format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}", t.year, t.month, t.day, t.hour, t.minute, t.second)),
+        iso: exif
+            .get_field(Tag::PhotographicSensitivity, In::PRIMARY)
+            .and_then(|f| f.value.get_uint(0)),
+        exposure_time: rational(&exif, Tag::ExposureTime).and_then(format_exposure),
+        f_number: rational(&exif, Tag::FNumber).map(|r| r.to_f64()),
+        focal_length: rational(&exif, Tag::FocalLength).map(|r| r.to_f64()),
+    })
+}
+
+fn ascii(exif: &Exif, tag: Tag) -> Option<String> {
+    let Value::Ascii(ref values) = exif.get_field(tag, In::PRIMARY)?.value else { return None };
+    let text = String::from_utf8_lossy(values.first()?).trim_end_matches('\0').trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+fn rational(exif: &Exif, tag: Tag) -> Option<Rational> {
+    match exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Rational(ref values) => values.first().copied().filter(|r| r.denom != 0 && r.num != 0),
+        _ => None,
+    }
+}
+
+fn format_exposure(r: Rational) -> Option<String> {
+    let seconds = r.to_f64();
+    if seconds < 1.0 {
+        Some(format!("1/{:.0}", 1.0 / seconds))
+    } else {
+        let text = format!("{:.1}", seconds);
+        Some(text.strip_suffix(".0").unwrap_or(&text).to_string())
+    }
+}
+
+/// The EXIF summary of an indexed image. Files the index pass hasn't reached
+/// yet are read on the spot; `None` means the file has no EXIF.
+#[tauri::command]
+pub fn get_image_exif(image_id: i64) -> Result<Option<ExifInfo>, String> {
+    let conn = connect_db()?;
+    let row = conn
+        .query_row(
+            "SELECT relative_path, filename, camera_make, camera_model, lens_model, taken_at,
+                    iso, exposure_time, f_number, focal_length
+             FROM images WHERE id = ?1",
+            params![image_id],
+            |row| {
+                let make: Option<String> = row.get(2)?;
+                let info = ExifInfo {
+                    make: make.clone().filter(|m| !m.is_empty()),
+                    model: row.get(3)?,
+                    lens: row.get(4)?,
+                    taken_at: row.get(5)?,
+                    iso: row.get(6)?,
+                    exposure_time: row.get(7)?,
+                    f_number: row.get(8)?,
+                    focal_length: row.get(9)?,
+                };
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, make.is_some(), info))
+            },
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+
+    let Some((dir, file_name, indexed, info)) = row else {
+        return Err(format!("Image {} not found", image_id));
+    };
+    let info = if indexed {
+        info
+    } else {
+        read(&Path::new(&dir).join(&file_name)).map_err(|e| format!("Failed to read EXIF: {}", e))?
+    };
+    Ok((!info.is_empty()).then_some(info))
+}
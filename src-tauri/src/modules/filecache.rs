@@ -1,37 +1,59 @@
-use crate::modules::db::TagInfo;
-use bincode::serialize;
+use crate::modules::animation;
+use crate::modules::db::{self, connect_db, TagInfo};
+use crate::modules::exifinfo;
+use crate::modules::mediatype::{self, MediaKind};
+use crate::modules::placeholder;
+use crate::{log_error, log_info};
 use chrono::{DateTime, Local};
 use lazy_static::lazy_static;
+use rayon::prelude::*;
+use rusqlite::{params, Connection, OptionalExtension};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::{self, File};
-use std::io::{self, Write};
+use std::io::{self, Read};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// The file index lives in the `images` table: one row per file, keyed by
+// (relative_path, filename), where relative_path is the category folder.
+// Rows created by `add_image` before the indexer has seen the file have a
+// NULL size and are not listed until they are indexed.
+
+const FILE_COLUMNS: &str = "relative_path, category, filename, size, modified_ms, created_ms, rating, \
+     media_kind, id, placeholder, width, height, frame_count, duration_ms";
+const HASH_BATCH: usize = 256;
+const PLACEHOLDER_BATCH: usize = 64;
+const EXIF_BATCH: usize = 256;
+
+static HASH_PASS_RUNNING: AtomicBool = AtomicBool::new(false);
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct FileInfo {
+    pub id: i64,
     pub name: String,
     pub category: String,
     pub filepath: String,
     pub size: u64,
+    pub modified_ms: u64,
     pub last_modified: String,
     pub created_at: String,
     pub root_path: String,
     pub tags: Option<Vec<TagInfo>>,
+    pub rating: Option<u8>,
+    pub media_kind: MediaKind,
+    // BlurHash and display size; None until the background pass reaches the file
+    pub placeholder: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub frame_count: Option<u32>,
+    pub duration_ms: Option<u64>,
 }
 
-#[derive(Debug, Clone)]
-struct CacheEntry {
-    files: Vec<FileInfo>,
-    last_modified: DateTime<Local>,
-}
-
-pub struct FileCache {
-    cache_dir: PathBuf,
-    cache: Arc<Mutex<HashMap<String, CacheEntry>>>,
-}
+pub struct FileCache;
 
 lazy_static! {
     static ref GLOBAL_CACHE: Arc<Mutex<Option<Arc<FileCache>>>> = Arc::new(Mutex::new(None));
@@ -48,12 +70,19 @@ impl FileCache {
         if !cache_dir.exists() {
             fs::create_dir_all(&cache_dir)?;
         }
-
-        let cache = Arc::new(FileCache {
-            cache_dir,
-            cache: Arc::new(Mutex::new(HashMap::new())),
-        });
-
+        remove_legacy_cache_files(&cache_dir);
+
+        // Decoders differ between builds, so a file this build can open may
+        // have been indexed as unsupported, or failed to decode, in another;
+        // have those re-sniffed and their placeholders retried
+        open_db()?
+            .execute_batch(
+                "UPDATE images SET media_kind = NULL WHERE media_kind = 'unsupported';
+                 UPDATE images SET placeholder = NULL WHERE placeholder = '';",
+            )
+            .map_err(to_io)?;
+
+        let cache = Arc::new(FileCache);
         *global = Some(cache.clone());
         Ok(cache)
     }
@@ -62,125 +91,90 @@ impl FileCache {
         GLOBAL_CACHE.lock().unwrap().clone()
     }
 
-    fn generate_cache_key(&self, root_path: &Path, category: &str) -> String {
-        let mut hasher = Sha256::new();
-        hasher.update(root_path.to_string_lossy().as_bytes());
-        hasher.update(category.as_bytes());
-        format!("{:x}", hasher.finalize())
-    }
+    pub fn get_files(&self, root_path: &Path, category: &str) -> io::Result<Vec<FileInfo>> {
+        let conn = open_db()?;
+        let root = root_path.to_string_lossy().to_string();
+
+        let categories = if category == "all" {
+            list_category_dirs(root_path)?
+        } else {
+            vec![category.to_string()]
+        };
+
+        let mut stmt = conn
+            .prepare_cached(&format!(
+                "SELECT {} FROM images WHERE relative_path = ?1 AND size IS NOT NULL",
+                FILE_COLUMNS
+            ))
+            .map_err(to_io)?;
 
-    pub fn get_cache_path(&self, root_path: &Path, category: &str) -> PathBuf {
-        let cache_key = self.generate_cache_key(root_path, category);
-        self.cache_dir.join(format!("{}_files.bin", cache_key))
+        let mut files = Vec::new();
+        for category in categories {
+            let dir = root_path.join(&category).to_string_lossy().to_string();
+            let rows = stmt
+                .query_map([&dir], |row| row_to_file_info(row, &root))
+                .map_err(to_io)?;
+            for row in rows {
+                files.push(row.map_err(to_io)?);
+            }
+        }
+        Ok(files)
     }
 
+    /// Re-lists one category folder (or every folder for "all") and syncs its rows.
     pub async fn refresh_category(
         &self,
         root_path: &Path,
         category: &str,
     ) -> io::Result<Vec<FileInfo>> {
-        let mut files = Vec::new();
-        let category_path = if category == "all" {
-            root_path.to_path_buf()
-        } else {
-            root_path.join(category)
-        };
+        let mut conn = open_db()?;
 
-        if category_path.is_dir() {
-            for entry in fs::read_dir(category_path)? {
-                let entry = entry?;
-                let metadata = entry.metadata()?;
-
-                if metadata.is_file() {
-                    let file_info = self.create_file_info(
-                        entry.file_name().to_string_lossy().to_string(),
-                        category.to_string(),
-                        &entry.path(),
-                        &metadata,
-                        root_path,
-                    )?;
-                    files.push(file_info);
-                }
+        if category == "all" {
+            for name in list_category_dirs(root_path)? {
+                sync_dir(&mut conn, root_path, &name, true)?;
             }
+        } else if root_path.join(category).is_dir() {
+            sync_dir(&mut conn, root_path, category, true)?;
         }
 
-        let cache_key = self.generate_cache_key(root_path, category);
-        {
-            let mut cache = self.cache.lock().unwrap();
-            cache.insert(cache_key, CacheEntry { files: files.clone(), last_modified: Local::now() });
-        }
-
-        self.write_cache(root_path, category, &files)?;
-        Ok(files)
+        self.spawn_hash_pass();
+        self.get_files(root_path, category)
     }
 
-    pub fn get_files(&self, root_path: &Path, category: &str) -> io::Result<Vec<FileInfo>> {
-        let cache_key = self.generate_cache_key(root_path, category);
-
-        {
-            let cache = self.cache.lock().unwrap();
-            if let Some(entry) = cache.get(&cache_key) {
-                return Ok(entry.files.clone());
+    /// Brings the index up to date with the disk. Folders whose mtime matches
+    /// the last sync only have their known files re-stat'ed; changed folders
+    /// are re-listed. Only rows whose size or mtime differ are rewritten.
+    /// Returns the categories that changed.
+    pub async fn reconcile(&self, root_path: &Path) -> io::Result<Vec<String>> {
+        let mut conn = open_db()?;
+        let categories = list_category_dirs(root_path)?;
+        let mut changed = Vec::new();
+
+        for category in &categories {
+            if sync_dir(&mut conn, root_path, category, false)? {
+                changed.push(category.clone());
             }
         }
 
-        if category == "all" {
-            let mut all_files = Vec::new();
-
-            for entry in fs::read_dir(root_path)? {
-                let entry = entry?;
-                if entry.path().is_dir() {
-                    let category_name = entry.file_name().to_string_lossy().to_string();
-
-                    for file_entry in fs::read_dir(root_path.join(&category_name))? {
-                        let file_entry = file_entry?;
-                        if file_entry.path().is_file() {
-                            let metadata = file_entry.metadata()?;
-                            all_files.push(self.create_file_info(
-                                file_entry.file_name().to_string_lossy().to_string(),
-                                category_name.clone(),
-                                &file_entry.path(),
-                                &metadata,
-                                root_path,
-                            )?);
-                        }
-                    }
-                }
-            }
+        // Drop rows whose category folder no longer exists
+        let indexed_dirs: Vec<String> = conn
+            .prepare("SELECT DISTINCT relative_path FROM images")
+            .and_then(|mut s| s.query_map([], |row| row.get(0))?.collect())
+            .map_err(to_io)?;
 
-            {
-                let mut cache = self.cache.lock().unwrap();
-                cache.insert(cache_key, CacheEntry { files: all_files.clone(), last_modified: Local::now() });
+        for dir in indexed_dirs {
+            let dir_path = PathBuf::from(&dir);
+            if dir_path.parent() != Some(root_path) || dir_path.exists() {
+                continue;
             }
-            self.write_cache(root_path, category, &all_files)?;
-            Ok(all_files)
-        } else {
-            let category_path = root_path.join(category);
-            let mut category_files = Vec::new();
-
-            if category_path.is_dir() {
-                for entry in fs::read_dir(&category_path)? {
-                    let entry = entry?;
-                    if entry.path().is_file() {
-                        let metadata = entry.metadata()?;
-                        category_files.push(self.create_file_info(
-                            entry.file_name().to_string_lossy().to_string(),
-                            category.to_string(),
-                            &entry.path(),
-                            &metadata,
-                            root_path,
-                        )?);
-                    }
-                }
-            }
-
-            {
-                let mut cache = self.cache.lock().unwrap();
-                cache.insert(cache_key, CacheEntry { files: category_files.clone(), last_modified: Local::now() });
+            if let Some(category) = dir_path.file_name().map(|n| n.to_string_lossy().to_string()) {
+                self.remove_category(root_path, &category)?;
+                changed.push(category);
             }
-            self.write_cache(root_path, category, &category_files)?;
-            Ok(category_files)
         }
+
+        self.spawn_hash_pass();
+        Ok(changed)
     }
 
     pub(crate) fn create_file_info(
@@ -195,50 +189,57 @@ impl FileCache {
         let creation_time = metadata.created()?;
 
         Ok(FileInfo {
+            id: 0,
             name: file_name,
             category,
             filepath: file_path.to_string_lossy().to_string(),
             size: metadata.len(),
+            modified_ms: system_time_ms(modified_time),
             last_modified: DateTime::<Local>::from(modified_time).format("%Y-%m-%d %H:%M:%S").to_string(),
             created_at: DateTime::<Local>::from(creation_time).format("%Y-%m-%d %H:%M:%S").to_string(),
             root_path: root_path.to_string_lossy().to_string(),
             tags: None,
+            rating: None,
+            media_kind: mediatype::detect(file_path).kind,
+            placeholder: None,
+            width: None,
+            height: None,
+            frame_count: None,
+            duration_ms: None,
         })
     }
 
-    fn write_cache(&self, root_path: &Path, category: &str, content: &[FileInfo]) -> io::Result<()> {
-        let cache_path = self.get_cache_path(root_path, category);
-
-        if let Some(cache_dir) = cache_path.parent() {
-            if !cache_dir.exists() {
-                fs::create_dir_all(cache_dir)?;
-            }
-        }
-
-        let serialized = serialize(content)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+    /// Inserts or refreshes the row for a single file and returns its id.
+    pub fn upsert_file(&self, root_path: &Path, category: &str, file_path: &Path) -> io::Result<i64> {
+        let metadata = fs::metadata(file_path)?;
+        let file_name = file_path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let dir = root_path.join(category).to_string_lossy().to_string();
+
+        let conn = open_db()?;
+        let id = upsert_row(&conn, &dir, category, &file_name, &metadata)?;
+        self.spawn_hash_pass();
+        Ok(id)
+    }
 
-        let mut file = File::create(cache_path)?;
-        file.write_all(&serialized)?;
-        Ok(())
+    /// Drops the row for a single file by full path.
+    pub fn remove_path(&self, _root_path: &Path, _category: &str, file_path: &Path) -> io::Result<()> {
+        db::remove_image_by_path(file_path).map_err(to_io)
     }
 
     pub fn remove_file(&self, root_path: &Path, category: &str, file_name: &str) -> io::Result<()> {
-        let cache_key = self.generate_cache_key(root_path, category);
-
-        {
-            let mut cache = self.cache.lock().unwrap();
-            if let Some(entry) = cache.get_mut(&cache_key) {
-                entry.files.retain(|f| f.name != file_name);
-                self.write_cache(root_path, category, &entry.files)?;
-            }
-        }
-
-        if category != "all" {
-            self.remove_file(root_path, "all", file_name)?;
-        }
+        self.remove_path(root_path, category, &root_path.join(category).join(file_name))
+    }
 
-        Ok(())
+    /// Re-points an existing row at its new location so its id, tags, rating
+    /// and hash survive the move, then refreshes its metadata.
+    pub fn move_path(&self, root_path: &Path, from: &Path, to: &Path, new_category: &str) -> io::Result<()> {
+        db::move_image_path(from, to, new_category).map_err(to_io)?;
+        // If the destination already had a row the UPDATE was ignored; drop the old one
+        db::remove_image_by_path(from).map_err(to_io)?;
+        self.upsert_file(root_path, new_category, to).map(|_| ())
     }
 
     pub async fn move_file(
@@ -248,118 +249,511 @@ impl FileCache {
         new_category: &str,
         file_name: &str,
     ) -> io::Result<()> {
-        self.remove_file(root_path, old_category, file_name)?;
-        self.refresh_category(root_path, new_category).await?;
-        self.update_all_category(root_path).await
+        let from = root_path.join(old_category).join(file_name);
+        let to = root_path.join(new_category).join(file_name);
+        self.move_path(root_path, &from, &to, new_category)
     }
 
-    pub async fn update_all_category(&self, root_path: &Path) -> io::Result<()> {
-        let mut all_files = Vec::new();
+    pub fn remove_category(&self, root_path: &Path, category: &str) -> io::Result<()> {
+        let dir = root_path.join(category).to_string_lossy().to_string();
+        let conn = open_db()?;
+        conn.execute("DELETE FROM images WHERE relative_path = ?1", [&dir])
+            .map_err(to_io)?;
+        conn.execute("DELETE FROM library_dirs WHERE path = ?1", [&dir])
+            .map_err(to_io)?;
+        Ok(())
+    }
 
-        for entry in fs::read_dir(root_path)? {
-            let entry = entry?;
-            if entry.path().is_dir() {
-                let category_name = entry.file_name().to_string_lossy().to_string();
-
-                for file_entry in fs::read_dir(root_path.join(&category_name))? {
-                    let file_entry = file_entry?;
-                    if file_entry.path().is_file() {
-                        let metadata = file_entry.metadata()?;
-                        all_files.push(self.create_file_info(
-                            file_entry.file_name().to_string_lossy().to_string(),
-                            category_name.clone(),
-                            &file_entry.path(),
-                            &metadata,
-                            root_path,
-                        )?);
-                    }
-                }
+    /// File count and total bytes per category folder, straight from the index.
+    pub fn category_totals(&self, root_path: &Path) -> io::Result<HashMap<String, (usize, u64)>> {
+        let conn = open_db()?;
+        let mut stmt = conn
+            .prepare(
+                "SELECT relative_path, COUNT(*), COALESCE(SUM(size), 0) FROM images
+                 WHERE size IS NOT NULL AND media_kind != 'other'
+                 GROUP BY relative_path",
+            )
+            .map_err(to_io)?;
+
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?, row.get::<_, i64>(2)?))
+            })
+            .map_err(to_io)?;
+
+        let mut totals = HashMap::new();
+        for row in rows {
+            let (dir, count, size) = row.map_err(to_io)?;
+            let dir = PathBuf::from(dir);
+            if dir.parent() != Some(root_path) {
+                continue;
+            }
+            if let Some(name) = dir.file_name() {
+                totals.insert(name.to_string_lossy().to_string(), (count as usize, size as u64));
             }
         }
-
-        let cache_key = self.generate_cache_key(root_path, "all");
-        {
-            let mut cache = self.cache.lock().unwrap();
-            cache.insert(cache_key, CacheEntry { files: all_files.clone(), last_modified: Local::now() });
-        }
-        self.write_cache(root_path, "all", &all_files)
+        Ok(totals)
     }
 
-    pub fn remove_category(&self, root_path: &Path, category: &str) -> io::Result<()> {
-        let cache_key = self.generate_cache_key(root_path, category);
-
-        // Evict in-memory entry
-        {
-            let mut cache = self.cache.lock().unwrap();
-            cache.remove(&cache_key);
+    /// Fills in missing content hashes, then placeholders and EXIF, in the background.
+    /// Only one pass runs at a time; rows changed mid-pass are picked up by
+    /// the next one.
+    pub fn spawn_hash_pass(&self) {
+        if HASH_PASS_RUNNING.swap(true, Ordering::AcqRel) {
+            return;
         }
+        std::thread::spawn(|| {
+            match run_hash_pass() {
+                Ok(0) => {}
+                Ok(n) => log_info!("Hashed {} indexed files", n),
+                Err(e) => log_error!("Hash pass failed: {}", e),
+            }
+            match run_placeholder_pass() {
+                Ok(0) => {}
+                Ok(n) => log_info!("Computed placeholders for {} indexed files", n),
+                Err(e) => log_error!("Placeholder pass failed: {}", e),
+            }
+            match run_exif_pass() {
+                Ok(0) => {}
+                Ok(n) => log_info!("Read EXIF of {} indexed files", n),
+                Err(e) => log_error!("EXIF pass failed: {}", e),
+            }
+            HASH_PASS_RUNNING.store(false, Ordering::Release);
+        });
+    }
+}
 
-        // Delete the on-disk .bin file
-        let bin_path = self.get_cache_path(root_path, category);
-        if bin_path.exists() {
-            fs::remove_file(&bin_path)?;
-        }
+pub fn get_or_init_cache(cache_dir: PathBuf) -> io::Result<Arc<FileCache>> {
+    if let Some(cache) = FileCache::get_instance() {
+        Ok(cache)
+    } else {
+        FileCache::initialize(cache_dir)
+    }
+}
 
-        // Rebuild the "all" cache without this category's files
-        let all_key = self.generate_cache_key(root_path, "all");
-        {
-            let mut cache = self.cache.lock().unwrap();
-            if let Some(entry) = cache.get_mut(&all_key) {
-                entry.files.retain(|f| f.category != category);
-                self.write_cache(root_path, "all", &entry.files)?;
-            }
+/// Streams a file through SHA-256.
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
         }
+        hasher.update(&buf[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
 
-        Ok(())
+/// The SHA-256 of a file's current contents. Reuses the indexed hash when
+/// the row's size and mtime still match; otherwise hashes the file and, if
+/// it is indexed, stores the result so the background pass can skip it.
+pub fn content_hash(path: &Path, metadata: &fs::Metadata) -> io::Result<String> {
+    let dir = path.parent().map(|p| p.to_string_lossy().to_string()).unwrap_or_default();
+    let file_name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let size = metadata.len() as i64;
+    let modified_ms = metadata.modified().map(system_time_ms)? as i64;
+
+    let conn = open_db()?;
+    let indexed: Option<String> = conn
+        .prepare_cached(
+            "SELECT hash FROM images
+             WHERE relative_path = ?1 AND filename = ?2 AND size = ?3 AND modified_ms = ?4",
+        )
+        .and_then(|mut s| s.query_row(params![dir, file_name, size, modified_ms], |row| row.get(0)).optional())
+        .map_err(to_io)?
+        .flatten();
+    if let Some(hash) = indexed {
+        return Ok(hash);
     }
 
-    pub async fn synchronize_cache(&self, root_path: &Path) -> io::Result<()> {
-        let mut all_files = Vec::new();
+    let hash = hash_file(path)?;
+    conn.execute(
+        "UPDATE images SET hash = ?1
+         WHERE relative_path = ?2 AND filename = ?3 AND size = ?4 AND modified_ms = ?5",
+        params![hash, dir, file_name, size, modified_ms],
+    )
+    .map_err(to_io)?;
+    Ok(hash)
+}
 
-        for entry in fs::read_dir(root_path)? {
-            let entry = entry?;
-            if entry.path().is_dir() {
-                let category_name = entry.file_name().to_string_lossy().to_string();
-                let mut category_files = Vec::new();
-
-                for file_entry in fs::read_dir(root_path.join(&category_name))? {
-                    let file_entry = file_entry?;
-                    if file_entry.path().is_file() {
-                        let metadata = file_entry.metadata()?;
-                        let file_info = self.create_file_info(
-                            file_entry.file_name().to_string_lossy().to_string(),
-                            category_name.clone(),
-                            &file_entry.path(),
-                            &metadata,
-                            root_path,
-                        )?;
-                        category_files.push(file_info.clone());
-                        all_files.push(file_info);
+// ── indexing ──────────────────────────────────────────────────────────────────
+
+// Syncs the rows of one category folder. Returns whether anything changed.
+fn sync_dir(conn: &mut Connection, root_path: &Path, category: &str, force: bool) -> io::Result<bool> {
+    let dir_path = root_path.join(category);
+    let dir = dir_path.to_string_lossy().to_string();
+
+    // Stat the folder before listing it, so anything added mid-scan bumps
+    // the mtime past the stored value and gets picked up next time
+    let dir_ms = system_time_ms(fs::metadata(&dir_path)?.modified()?);
+    let saved_ms: Option<i64> = conn
+        .query_row("SELECT modified_ms FROM library_dirs WHERE path = ?1", [&dir], |row| row.get(0))
+        .optional()
+        .map_err(to_io)?;
+
+    let known: HashMap<String, Stamp> = conn
+        .prepare("SELECT filename, size, modified_ms, media_kind FROM images WHERE relative_path = ?1")
+        .and_then(|mut s| {
+            s.query_map([&dir], |row| {
+                Ok((row.get(0)?, Stamp { size: row.get(1)?, modified_ms: row.get(2)?, media_kind: row.get(3)? }))
+            })?
+            .collect()
+        })
+        .map_err(to_io)?;
+
+    let tx = conn.transaction().map_err(to_io)?;
+    let mut changed = false;
+
+    if !force && saved_ms == Some(dir_ms as i64) {
+        // Listing unchanged: only contents of known files can have changed
+        for (name, stamp) in &known {
+            match fs::metadata(dir_path.join(name)) {
+                Ok(metadata) if metadata.is_file() => {
+                    if !is_unchanged(stamp, &metadata) {
+                        upsert_row(&tx, &dir, category, name, &metadata)?;
+                        changed = true;
                     }
                 }
-
-                let cache_key = self.generate_cache_key(root_path, &category_name);
-                {
-                    let mut cache = self.cache.lock().unwrap();
-                    cache.insert(cache_key, CacheEntry { files: category_files.clone(), last_modified: Local::now() });
+                _ => {
+                    delete_row(&tx, &dir, name)?;
+                    changed = true;
                 }
-                self.write_cache(root_path, &category_name, &category_files)?;
             }
         }
+    } else {
+        let mut seen = HashSet::new();
+        for entry in fs::read_dir(&dir_path)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if !metadata.is_file() {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !known.get(&name).is_some_and(|stamp| is_unchanged(stamp, &metadata)) {
+                upsert_row(&tx, &dir, category, &name, &metadata)?;
+                changed = true;
+            }
+            seen.insert(name);
+        }
+        for name in known.keys().filter(|n| !seen.contains(*n)) {
+            delete_row(&tx, &dir, name)?;
+            changed = true;
+        }
+    }
+
+    tx.execute(
+        "INSERT INTO library_dirs (path, modified_ms) VALUES (?1, ?2)
+         ON CONFLICT(path) DO UPDATE SET modified_ms = excluded.modified_ms",
+        params![dir, dir_ms as i64],
+    )
+    .map_err(to_io)?;
+    tx.commit().map_err(to_io)?;
+    Ok(changed)
+}
+
+// The hashes, placeholder and EXIF are kept only while size and mtime are unchanged;
+// SET expressions see the old row, so the comparison happens before the new
+// values land.
+fn upsert_row(
+    conn: &Connection,
+    dir: &str,
+    category: &str,
+    file_name: &str,
+    metadata: &fs::Metadata,
+) -> io::Result<i64> {
+    let modified_ms = metadata.modified().map(system_time_ms)?;
+    let created_ms = metadata.created().map(system_time_ms).unwrap_or(modified_ms);
+    let media_kind = mediatype::detect(&Path::new(dir).join(file_name)).kind;
+
+    conn.prepare_cached(
+        "INSERT INTO images (relative_path, category, filename, size, modified_ms, created_ms, media_kind)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+         ON CONFLICT(relative_path, filename) DO UPDATE SET
+             category = excluded.category,
+             hash = CASE WHEN images.size IS excluded.size
+                          AND images.modified_ms IS excluded.modified_ms
+                         THEN images.hash ELSE NULL END,
+             placeholder = CASE WHEN images.size IS excluded.size
+                                 AND images.modified_ms IS excluded.modified_ms
+                                THEN images.placeholder ELSE NULL END,
+             camera_make = CASE WHEN images.size IS excluded.size
+                                 AND images.modified_ms IS excluded.modified_ms
+                                THEN images.camera_make ELSE NULL END,
+             phash = CASE WHEN images.size IS excluded.size
+                           AND images.modified_ms IS excluded.modified_ms
+                          THEN images.phash ELSE NULL END,
+             size = excluded.size,
+             modified_ms = excluded.modified_ms,
+             created_ms = excluded.created_ms,
+             media_kind = excluded.media_kind
+         RETURNING id",
+    )
+    .and_then(|mut s| {
+        s.query_row(params![
+            dir,
+            category,
+            file_name,
+            metadata.len() as i64,
+            modified_ms as i64,
+            created_ms as i64,
+            media_kind.as_str()
+        ], |row| row.get(0))
+    })
+    .map_err(to_io)
+}
+
+fn delete_row(conn: &Connection, dir: &str, file_name: &str) -> io::Result<()> {
+    conn.execute(
+        "DELETE FROM images WHERE relative_path = ?1 AND filename = ?2",
+        [dir, file_name],
+    )
+    .map_err(to_io)?;
+    Ok(())
+}
 
-        let all_cache_key = self.generate_cache_key(root_path, "all");
-        {
-            let mut cache = self.cache.lock().unwrap();
-            cache.insert(all_cache_key, CacheEntry { files: all_files.clone(), last_modified: Local::now() });
+fn run_hash_pass() -> Result<usize, String> {
+    let conn = connect_db()?;
+    let mut last_id = 0i64;
+    let mut hashed = 0;
+
+    loop {
+        let batch: Vec<(i64, String, String, i64, i64)> = conn
+            .prepare(
+                "SELECT id, relative_path, filename, size, modified_ms FROM images
+                 WHERE hash IS NULL AND size IS NOT NULL AND media_kind != 'other' AND id > ?1
+                 ORDER BY id LIMIT ?2",
+            )
+            .and_then(|mut s| {
+                s.query_map(params![last_id, HASH_BATCH as i64], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?))
+                })?
+                .collect()
+            })
+            .map_err(|e| e.to_string())?;
+
+        let Some(last) = batch.last() else { break };
+        last_id = last.0;
+
+        for (id, dir, file_name, size, modified_ms) in batch {
+            // Unreadable files are skipped and retried on the next pass
+            let Ok(hash) = hash_file(&Path::new(&dir).join(&file_name)) else { continue };
+            conn.execute(
+                "UPDATE images SET hash = ?1 WHERE id = ?2 AND size = ?3 AND modified_ms = ?4",
+                params![hash, id, size, modified_ms],
+            )
+            .map_err(|e| e.to_string())?;
+            hashed += 1;
         }
-        self.write_cache(root_path, "all", &all_files)
     }
+    Ok(hashed)
 }
 
-pub fn get_or_init_cache(cache_dir: PathBuf) -> io::Result<Arc<FileCache>> {
-    if let Some(cache) = FileCache::get_instance() {
-        Ok(cache)
-    } else {
-        FileCache::initialize(cache_dir)
+// Placeholders need a (reduced) decode, so each batch is computed in
+// parallel. Files that fail to decode get an empty placeholder so they
+// aren't retried until their contents change. Animated images also get
+// their frame count and duration here (0 when the frames can't be read).
+fn run_placeholder_pass() -> Result<usize, String> {
+    let conn = connect_db()?;
+    let mut last_id = 0i64;
+    let mut computed = 0;
+
+    loop {
+        let batch: Vec<(i64, String, String, i64, i64, String)> = conn
+            .prepare(
+                "SELECT id, relative_path, filename, size, modified_ms, media_kind FROM images
+                 WHERE placeholder IS NULL AND size IS NOT NULL
+                   AND media_kind IN ('image', 'animated_image') AND id > ?1
+                 ORDER BY id LIMIT ?2",
+            )
+            .and_then(|mut s| {
+                s.query_map(params![last_id, PLACEHOLDER_BATCH as i64], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?))
+                })?
+                .collect()
+            })
+            .map_err(|e| e.to_string())?;
+
+        let Some(last) = batch.last() else { break };
+        last_id = last.0;
+
+        let results: Vec<_> = batch
+            .into_par_iter()
+            .map(|(id, dir, file_name, size, modified_ms, media_kind)| {
+                let path = Path::new(&dir).join(&file_name);
+                let result = placeholder::compute(&path);
+                let animation = (MediaKind::parse(&media_kind) == MediaKind::AnimatedImage).then(|| {
+                    animation::info(&path)
+                        .map(|info| (info.frame_count, info.duration_ms as i64))
+                        .unwrap_or((0, 0))
+                });
+                (id, size, modified_ms, result, animation)
+            })
+            .collect();
+
+        for (id, size, modified_ms, result, animation) in results {
+            let (hash, width, height) = match result {
+                Ok(p) => (p.blurhash, Some(p.width), Some(p.height)),
+                Err(_) => (String::new(), None, None),
+            };
+            let (frame_count, duration_ms) = animation.unzip();
+            conn.execute(
+                "UPDATE images SET placeholder = ?1, width = ?2, height = ?3,
+                                   frame_count = ?4, duration_ms = ?5
+                 WHERE id = ?6 AND size = ?7 AND modified_ms = ?8",
+                params![hash, width, height, frame_count, duration_ms, id, size, modified_ms],
+            )
+            .map_err(|e| e.to_string())?;
+            computed += 1;
+        }
+    }
+    Ok(computed)
+}
+
+// EXIF needs no decode, just the metadata blocks. A file without any gets
+// an empty `camera_make` so it isn't read again until its contents change.
+fn run_exif_pass() -> Result<usize, String> {
+    let conn = connect_db()?;
+    let mut last_id = 0i64;
+    let mut read = 0;
+
+    loop {
+        let batch: Vec<(i64, String, String, i64, i64)> = conn
+            .prepare(
+                "SELECT id, relative_path, filename, size, modified_ms FROM images
+                 WHERE camera_make IS NULL AND size IS NOT NULL
+                   AND media_kind IN ('image', 'animated_image') AND id > ?1
+                 ORDER BY id LIMIT ?2",
+            )
+            .and_then(|mut s| {
+                s.query_map(params![last_id, EXIF_BATCH as i64], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?))
+                })?
+                .collect()
+            })
+            .map_err(|e| e.to_string())?;
+
+        let Some(last) = batch.last() else { break };
+        last_id = last.0;
+
+        let results: Vec<_> = batch
+            .into_par_iter()
+            .map(|(id, dir, file_name, size, modified_ms)| {
+                let info = exifinfo::read(&Path::new(&dir).join(&file_name)).unwrap_or_default();
+                (id, size, modified_ms, info)
+            })
+            .collect();
+
+        for (id, size, modified_ms, info) in results {
+            conn.execute(
+                "UPDATE images SET camera_make = ?1, camera_model = ?2, lens_model = ?3, taken_at = ?4,
+                                   iso = ?5, exposure_time = ?6, f_number = ?7, focal_length = ?8
+                 WHERE id = ?9 AND size = ?10 AND modified_ms = ?11",
+                params![
+                    info.make.unwrap_or_default(),
+                    info.model,
+                    info.lens,
+                    info.taken_at,
+                    info.iso,
+                    info.exposure_time,
+                    info.f_number,
+                    info.focal_length,
+                    id,
+                    size,
+                    modified_ms
+                ],
+            )
+            .map_err(|e| e.to_string())?;
+            read += 1;
+        }
+    }
+    Ok(read)
+}
+
+// ── helpers ───────────────────────────────────────────────────────────────────
+
+fn row_to_file_info(row: &rusqlite::Row, root: &str) -> rusqlite::Result<FileInfo> {
+    let dir: String = row.get(0)?;
+    let name: String = row.get(2)?;
+    let modified_ms = row.get::<_, i64>(4)? as u64;
+    let created_ms = row.get::<_, Option<i64>>(5)?.map(|v| v as u64).unwrap_or(modified_ms);
+    let rating: u8 = row.get(6)?;
+    let media_kind = row.get::<_, Option<String>>(7)?;
+
+    Ok(FileInfo {
+        id: row.get(8)?,
+        filepath: Path::new(&dir).join(&name).to_string_lossy().to_string(),
+        name,
+        category: row.get(1)?,
+        size: row.get::<_, i64>(3)? as u64,
+        modified_ms,
+        last_modified: format_ms(modified_ms),
+        created_at: format_ms(created_ms),
+        root_path: root.to_string(),
+        tags: None,
+        rating: (rating > 0).then_some(rating),
+        media_kind: media_kind.as_deref().map(MediaKind::parse).unwrap_or(MediaKind::Other),
+        placeholder: row.get::<_, Option<String>>(9)?.filter(|p| !p.is_empty()),
+        width: row.get(10)?,
+        height: row.get(11)?,
+        frame_count: row.get::<_, Option<u32>>(12)?.filter(|&n| n > 0),
+        duration_ms: row.get::<_, Option<i64>>(13)?.filter(|&ms| ms > 0).map(|ms| ms as u64),
+    })
+}
+
+/// Category folders directly under the root; "temp" is scratch space.
+pub fn list_category_dirs(root_path: &Path) -> io::Result<Vec<String>> {
+    let mut categories = Vec::new();
+    for entry in fs::read_dir(root_path)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() && entry.file_name() != "temp" {
+            categories.push(entry.file_name().to_string_lossy().to_string());
+        }
     }
+    Ok(categories)
+}
+
+// The bincode listings written by earlier versions are no longer read.
+fn remove_legacy_cache_files(cache_dir: &Path) {
+    let Ok(entries) = fs::read_dir(cache_dir) else { return };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.ends_with("_files.bin") || name.ends_with("_files.bin.tmp") {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+fn open_db() -> io::Result<Connection> {
+    connect_db().map_err(to_io)
+}
+
+fn to_io(e: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e.to_string())
+}
+
+fn system_time_ms(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+fn format_ms(ms: u64) -> String {
+    DateTime::<Local>::from(UNIX_EPOCH + Duration::from_millis(ms))
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+// What the index last saw of a file; a missing media kind forces a re-sniff.
+struct Stamp {
+    size: Option<i64>,
+    modified_ms: Option<i64>,
+    media_kind: Option<String>,
+}
+
+fn is_unchanged(stamp: &Stamp, metadata: &fs::Metadata) -> bool {
+    let modified = metadata.modified().map(|t| system_time_ms(t) as i64).ok();
+    stamp.size == Some(metadata.len() as i64)
+        && stamp.modified_ms.is_some()
+        && stamp.modified_ms == modified
+        && stamp.media_kind.is_some()
 }
@@ -1,6 +1,8 @@
 use super::filecache::{get_or_init_cache, FileInfo};
+use super::filequery::{run_query, FileQuery, TagFilterSets};
 use crate::modules::config::get_config;
 use crate::modules::pathutils::get_main_path;
+use crate::modules::watcher::{emit_library_changed, LibraryChangedEvent};
 use crate::{log_error, log_info};
 use serde::{Deserialize, Serialize};
 use std::fs::{self};
@@ -39,6 +41,7 @@ pub struct FileResponse {
     pub current_page: u32,
     pub total_pages: u32,
     pub total_files: usize,
+    pub next_cursor: Option<String>,
 }
 
 pub fn initialize_cache() -> io::Result<()> {
@@ -52,11 +55,17 @@ pub fn initialize_cache() -> io::Result<()> {
         fs::create_dir_all(&root_folder_path)?;
     }
 
+    // The index already holds the last session's listing; catch up in the background
     std::thread::spawn(move || {
         let runtime = tokio::runtime::Runtime::new().unwrap();
         runtime.block_on(async {
-            if let Err(e) = cache.synchronize_cache(&root_folder_path).await {
-                log_error!("Failed to synchronize cache: {}", e);
+            match cache.reconcile(&root_folder_path).await {
+                Ok(changed) if !changed.is_empty() => {
+                    log_info!("Reconciled file index, {} categories changed", changed.len());
+                    emit_library_changed(LibraryChangedEvent { categories: changed, ..Default::default() });
+                }
+                Ok(_) => {}
+                Err(e) => log_error!("Failed to reconcile file index: {}", e),
             }
         });
     });
@@ -69,6 +78,7 @@ pub async fn get_files(
     page: u32,
     limit: Option<i32>,
     category: Option<String>,
+    query: Option<FileQuery>,
 ) -> Result<FileResponse, String> {
     use crate::modules::db;
 
@@ -82,6 +92,7 @@ pub async fn get_files(
     }
 
     let category = category.unwrap_or_else(|| "all".to_string());
+    let query = query.unwrap_or_default();
 
     let mut cached_files = cache
         .get_files(&root_folder_path, &category)
@@ -94,7 +105,30 @@ pub async fn get_files(
             .map_err(|e| format!("Error refreshing cache: {}", e))?;
     }
 
-    let file_paths: Vec<(PathBuf, String)> = cached_files
+    let included = query.included_tags();
+    let excluded = query.excluded_tags();
+    let tag_sets = TagFilterSets {
+        required: (!included.is_empty())
+            .then(|| db::get_image_paths_with_all_tags(&included))
+            .transpose()
+            .map_err(|e| format!("Error filtering by tags: {}", e))?,
+        excluded: (!excluded.is_empty())
+            .then(|| db::get_image_paths_with_any_tag(&excluded))
+            .transpose()
+            .map_err(|e| format!("Error filtering by tags: {}", e))?,
+        tagged: query
+            .untagged_only
+            .then(db::get_tagged_image_paths)
+            .transpose()
+            .map_err(|e| format!("Error filtering untagged files: {}", e))?,
+    };
+
+    let result = run_query(&cached_files, &query, &tag_sets, page, limit)?;
+
+    // Tags are only looked up for the page being returned
+    let mut files: Vec<FileInfo> = result.files.into_iter().cloned().collect();
+
+    let file_paths: Vec<(PathBuf, String)> = files
         .iter()
         .map(|file| (PathBuf::from(&file.filepath), file.category.clone()))
         .collect();
@@ -105,7 +139,7 @@ pub async fn get_files(
     let tags_map = db::get_batch_image_tags(&image_ids)
         .map_err(|e| format!("Error getting tags: {}", e))?;
 
-    for file in &mut cached_files {
+    for file in &mut files {
         if let Some(image_id) = image_ids.get(&PathBuf::from(&file.filepath)) {
             file.tags = Some((&tags_map.get(image_id)).cloned().unwrap_or_default());
         } else {
@@ -113,26 +147,19 @@ pub async fn get_files(
         }
     }
 
-    let total_files = cached_files.len();
-    let total_pages = if let Some(lim) = limit {
-        if lim == -1 { 1 } else { (total_files as f32 / lim as f32).ceil() as u32 }
-    } else {
-        1
-    };
-
-    let files = if let Some(lim) = limit {
-        if lim == -1 {
-            cached_files
-        } else {
-            let start = ((page - 1) * lim as u32) as usize;
-            let end = std::cmp::min((page * lim as u32) as usize, total_files);
-            if start < total_files { cached_files[start..end].to_vec() } else { Vec::new() }
-        }
-    } else {
-        cached_files
+    let total_files = result.total;
+    let total_pages = match limit {
+        Some(lim) if lim > 0 => (total_files as f32 / lim as f32).ceil() as u32,
+        _ => 1,
     };
 
-    Ok(FileResponse { files, current_page: page, total_pages, total_files })
+    Ok(FileResponse {
+        files,
+        current_page: page,
+        total_pages,
+        total_files,
+        next_cursor: result.next_cursor,
+    })
 }
 
 #[tauri::command]
@@ -215,15 +242,13 @@ pub async fn move_file(
 
     log_info!("File {} moved to category: {}", file_name, category);
 
-    let file_info = cache
+    let id = cache.upsert_file(&root_folder_path, &category, &target_path)
+        .map_err(|e| format!("Error updating file index: {}", e))?;
+
+    let mut file_info = cache
         .create_file_info(file_name.clone(), category.clone(), &target_path, &stats, &root_folder_path)
         .map_err(|e| format!("Error creating file info: {}", e))?;
-
-    cache.refresh_category(&root_folder_path, &category).await
-        .map_err(|e| format!("Error refreshing category cache: {}", e))?;
-
-    cache.update_all_category(&root_folder_path).await
-        .map_err(|e| format!("Error updating all category cache: {}", e))?;
+    file_info.id = id;
 
     Ok(FileMoveResponse { success: true, file: file_info })
 }
@@ -289,9 +314,6 @@ pub async fn move_file_category(
     cache.move_file(&root_folder_path, &old_category, &new_category, &file_name).await
         .map_err(|e| format!("Error updating cache: {}", e))?;
 
-    cache.update_all_category(&root_folder_path).await
-        .map_err(|e| format!("Error updating all category cache: {}", e))?;
-
     Ok(MoveFileCategoryResponse { success: true })
 }
 
@@ -350,15 +372,13 @@ pub async fn save_and_move_file(
     app.emit("upload-finished", UploadProgress { filename: file_name.clone(), progress: 100.0, status: "complete".to_string() })
         .map_err(|e| format!("Failed to emit progress: {}", e))?;
 
-    let file_info = cache
+    let id = cache.upsert_file(&root_folder_path, &category, &target_path)
+        .map_err(|e| format!("Error updating file index: {}", e))?;
+
+    let mut file_info = cache
         .create_file_info(file_name.clone(), category.clone(), &target_path, &stats, &root_folder_path)
         .map_err(|e| format!("Error creating file info: {}", e))?;
-
-    cache.refresh_category(&root_folder_path, &category).await
-        .map_err(|e| format!("Error refreshing category cache: {}", e))?;
-
-    cache.update_all_category(&root_folder_path).await
-        .map_err(|e| format!("Error updating all category cache: {}", e))?;
+    file_info.id = id;
 
     Ok(FileMoveResponse { success: true, file: file_info })
 }
@@ -0,0 +1,331 @@
+use crate::modules::filecache::FileInfo;
+use crate::modules::mediatype::MediaKind;
+use base64::{engine::general_purpose, Engine as _};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+// ── request types ─────────────────────────────────────────────────────────────
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    #[default]
+    Name,
+    Size,
+    Modified,
+    Created,
+    Rating,
+    Random,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Sorting, filtering and cursor options for `get_files`.
+///
+/// Dates are compared against the `"%Y-%m-%d %H:%M:%S"` strings stored in
+/// `FileInfo`, so a plain `"2024-05-01"` works as a day boundary.
+/// Tags prefixed with `-` are excluded; all other tags must be present.
+/// Without `media_kinds`, everything except non-media files is listed.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct FileQuery {
+    #[serde(default)]
+    pub sort_by: SortKey,
+    #[serde(default)]
+    pub order: SortOrder,
+    pub seed: Option<u64>,
+    pub extensions: Option<Vec<String>>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    pub modified_after: Option<String>,
+    pub modified_before: Option<String>,
+    pub created_after: Option<String>,
+    pub created_before: Option<String>,
+    pub tags: Option<Vec<String>>,
+    #[serde(default)]
+    pub untagged_only: bool,
+    pub media_kinds: Option<Vec<MediaKind>>,
+    pub cursor: Option<String>,
+}
+
+/// Tag sets resolved from the DB before filtering, keyed by full file path.
+#[derive(Default)]
+pub struct TagFilterSets {
+    pub required: Option<HashSet<PathBuf>>,
+    pub excluded: Option<HashSet<PathBuf>>,
+    pub tagged: Option<HashSet<PathBuf>>,
+}
+
+impl FileQuery {
+    pub fn included_tags(&self) -> Vec<String> {
+        self.tags
+            .iter()
+            .flatten()
+            .filter(|t| !t.starts_with('-'))
+            .cloned()
+            .collect()
+    }
+
+    pub fn excluded_tags(&self) -> Vec<String> {
+        self.tags
+            .iter()
+            .flatten()
+            .filter_map(|t| t.strip_prefix('-'))
+            .filter(|t| !t.is_empty())
+            .map(|t| t.to_string())
+            .collect()
+    }
+
+    fn seed(&self) -> u64 {
+        self.seed.unwrap_or(0)
+    }
+
+    fn matches(&self, file: &FileInfo, tag_sets: &TagFilterSets) -> bool {
+        match &self.media_kinds {
+            Some(kinds) if !kinds.contains(&file.media_kind) => return false,
+            None if file.media_kind == MediaKind::Other => return false,
+            _ => {}
+        }
+        if let Some(exts) = &self.extensions {
+            let ext = file_extension(&file.name);
+            if !exts.iter().any(|e| e.trim_start_matches('.').eq_ignore_ascii_case(&ext)) {
+                return false;
+            }
+        }
+        if self.min_size.is_some_and(|min| file.size < min) {
+            return false;
+        }
+        if self.max_size.is_some_and(|max| file.size > max) {
+            return false;
+        }
+        if let Some(after) = &self.modified_after {
+            if file.last_modified.as_str() < after.as_str() {
+                return false;
+            }
+        }
+        if let Some(before) = &self.modified_before {
+            if file.last_modified.as_str() >= before.as_str() {
+                return false;
+            }
+        }
+        if let Some(after) = &self.created_after {
+            if file.created_at.as_str() < after.as_str() {
+                return false;
+            }
+        }
+        if let Some(before) = &self.created_before {
+            if file.created_at.as_str() >= before.as_str() {
+                return false;
+            }
+        }
+
+        let path = PathBuf::from(&file.filepath);
+        if let Some(required) = &tag_sets.required {
+            if !required.contains(&path) {
+                return false;
+            }
+        }
+        if let Some(excluded) = &tag_sets.excluded {
+            if excluded.contains(&path) {
+                return false;
+            }
+        }
+        if let Some(tagged) = &tag_sets.tagged {
+            if tagged.contains(&path) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+// ── sort values ───────────────────────────────────────────────────────────────
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+enum SortValue {
+    Num(u64),
+    Text(String),
+}
+
+fn sort_value(file: &FileInfo, key: SortKey, seed: u64) -> SortValue {
+    match key {
+        SortKey::Name => SortValue::Text(file.name.clone()),
+        SortKey::Size => SortValue::Num(file.size),
+        SortKey::Modified => SortValue::Text(file.last_modified.clone()),
+        SortKey::Created => SortValue::Text(file.created_at.clone()),
+        SortKey::Rating => SortValue::Num(file.rating.unwrap_or(0) as u64),
+        SortKey::Random => SortValue::Num(random_rank(&file.filepath, seed)),
+    }
+}
+
+fn compare_values(key: SortKey, a: &SortValue, b: &SortValue) -> Ordering {
+    match (a, b) {
+        (SortValue::Num(x), SortValue::Num(y)) => x.cmp(y),
+        (SortValue::Text(x), SortValue::Text(y)) if key == SortKey::Name => natural_cmp(x, y),
+        (SortValue::Text(x), SortValue::Text(y)) => x.cmp(y),
+        (SortValue::Num(_), SortValue::Text(_)) => Ordering::Less,
+        (SortValue::Text(_), SortValue::Num(_)) => Ordering::Greater,
+    }
+}
+
+// FNV-1a over the path, mixed with the seed through splitmix64. Unlike
+// `DefaultHasher` this is stable across builds, so a seed keeps its order.
+fn random_rank(path: &str, seed: u64) -> u64 {
+    let mut h: u64 = 0xcbf29ce484222325;
+    for b in path.as_bytes() {
+        h ^= *b as u64;
+        h = h.wrapping_mul(0x100000001b3);
+    }
+    let mut z = h ^ seed.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// Case-insensitive comparison that orders digit runs by numeric value,
+/// so `img2.png` sorts before `img10.png`.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut ai = a.chars().peekable();
+    let mut bi = b.chars().peekable();
+
+    loop {
+        match (ai.peek().copied(), bi.peek().copied()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(ca), Some(cb)) if ca.is_ascii_digit() && cb.is_ascii_digit() => {
+                let da: String = std::iter::from_fn(|| ai.next_if(|c| c.is_ascii_digit())).collect();
+                let db: String = std::iter::from_fn(|| bi.next_if(|c| c.is_ascii_digit())).collect();
+                let ta = da.trim_start_matches('0');
+                let tb = db.trim_start_matches('0');
+                let ord = ta.len().cmp(&tb.len()).then_with(|| ta.cmp(tb));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+            (Some(ca), Some(cb)) => {
+                let ord = ca.to_lowercase().cmp(cb.to_lowercase());
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+                ai.next();
+                bi.next();
+            }
+        }
+    }
+}
+
+fn file_extension(name: &str) -> String {
+    std::path::Path::new(name)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+// ── cursors ───────────────────────────────────────────────────────────────────
+//
+// A cursor records the sort value and path of the last item on a page, plus
+// the sort it was produced under. The next page starts strictly after that
+// position, so inserts and deletes elsewhere never shift or repeat items.
+
+#[derive(Serialize, Deserialize)]
+struct Cursor {
+    s: String,
+    k: SortValue,
+    p: String,
+}
+
+fn sort_signature(query: &FileQuery) -> String {
+    format!("{:?}:{:?}:{}", query.sort_by, query.order, query.seed())
+}
+
+fn encode_cursor(query: &FileQuery, value: SortValue, path: &str) -> String {
+    let cursor = Cursor { s: sort_signature(query), k: value, p: path.to_string() };
+    let json = serde_json::to_vec(&cursor).unwrap_or_default();
+    general_purpose::URL_SAFE_NO_PAD.encode(json)
+}
+
+fn decode_cursor(query: &FileQuery, raw: &str) -> Result<Cursor, String> {
+    let bytes = general_purpose::URL_SAFE_NO_PAD
+        .decode(raw)
+        .map_err(|_| "Invalid cursor".to_string())?;
+    let cursor: Cursor = serde_json::from_slice(&bytes).map_err(|_| "Invalid cursor".to_string())?;
+    if cursor.s != sort_signature(query) {
+        return Err("Cursor does not match the requested sort".to_string());
+    }
+    Ok(cursor)
+}
+
+// ── query execution ───────────────────────────────────────────────────────────
+
+pub struct QueryResult<'a> {
+    pub files: Vec<&'a FileInfo>,
+    pub total: usize,
+    pub next_cursor: Option<String>,
+}
+
+/// Filters and sorts `files`, then slices one page either after `query.cursor`
+/// or by page number. `limit == None` or `-1` returns everything that matched.
+pub fn run_query<'a>(
+    files: &'a [FileInfo],
+    query: &FileQuery,
+    tag_sets: &TagFilterSets,
+    page: u32,
+    limit: Option<i32>,
+) -> Result<QueryResult<'a>, String> {
+    let seed = query.seed();
+    let key = query.sort_by;
+
+    let mut keyed: Vec<(SortValue, &FileInfo)> = files
+        .iter()
+        .filter(|f| query.matches(f, tag_sets))
+        .map(|f| (sort_value(f, key, seed), f))
+        .collect();
+
+    let order = |a: &SortValue, pa: &str, b: &SortValue, pb: &str| {
+        let ord = compare_values(key, a, b);
+        let ord = if query.order == SortOrder::Desc { ord.reverse() } else { ord };
+        ord.then_with(|| pa.cmp(pb))
+    };
+
+    keyed.sort_by(|(a, fa), (b, fb)| order(a, &fa.filepath, b, &fb.filepath));
+
+    let total = keyed.len();
+    let page_size = match limit {
+        Some(lim) if lim > 0 => lim as usize,
+        _ => total,
+    };
+
+    let start = match &query.cursor {
+        Some(raw) => {
+            let cursor = decode_cursor(query, raw)?;
+            keyed.partition_point(|(v, f)| {
+                order(v, &f.filepath, &cursor.k, &cursor.p) != Ordering::Greater
+            })
+        }
+        None => (page.max(1) as usize - 1).saturating_mul(page_size),
+    };
+
+    let end = start.saturating_add(page_size).min(total);
+    let slice = if start < total { &keyed[start..end] } else { &[][..] };
+
+    let next_cursor = if end < total {
+        slice.last().map(|(v, f)| encode_cursor(query, v.clone(), &f.filepath))
+    } else {
+        None
+    };
+
+    Ok(QueryResult {
+        files: slice.iter().map(|(_, f)| *f).collect(),
+        total,
+        next_cursor,
+    })
+}
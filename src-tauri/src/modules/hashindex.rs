@@ -0,0 +1,104 @@
+// Multi-index hashing over 64-bit perceptual hashes, for radius queries in
+// Hamming distance without comparing against every hash.
+//
+// Each hash is split into CHUNKS 16-bit substrings, and each substring
+// position gets its own table from substring value to items. If two hashes
+// are within `r` bits, then by pigeonhole at least one of their substrings
+// is within `r / CHUNKS` bits, so a query only has to look up the buckets
+// of its substrings flipped in at most that many bits, then verify the
+// candidates. At the radii duplicate detection uses (9 bits for a 0.85
+// threshold) that is at most 137 buckets per substring, each holding about
+// n / 65536 items on well-spread hashes.
+//
+// Items are indices into the caller's own list, in insertion order.
+
+const CHUNKS: usize = 4;
+const CHUNK_BITS: u32 = 16;
+const BUCKETS: usize = 1 << CHUNK_BITS;
+
+pub struct HashIndex {
+    hashes: Vec<u64>,
+    // Per chunk, a bucket's items are `items[offsets[v]..offsets[v + 1]]`
+    offsets: Vec<Vec<u32>>,
+    items: Vec<Vec<u32>>,
+}
+
+impl HashIndex {
+    pub fn new(hashes: Vec<u64>) -> HashIndex {
+        let mut offsets = Vec::with_capacity(CHUNKS);
+        let mut items = Vec::with_capacity(CHUNKS);
+
+        // Counting sort of item indices by substring value
+        for chunk in 0..CHUNKS {
+            let mut starts = vec![0u32; BUCKETS + 1];
+            for &hash in &hashes {
+                starts[substring(hash, chunk) as usize + 1] += 1;
+            }
+            for v in 0..BUCKETS {
+                starts[v + 1] += starts[v];
+            }
+            let mut next = starts.clone();
+            let mut sorted = vec![0u32; hashes.len()];
+            for (i, &hash) in hashes.iter().enumerate() {
+                let slot = &mut next[substring(hash, chunk) as usize];
+                sorted[*slot as usize] = i as u32;
+                *slot += 1;
+            }
+            offsets.push(starts);
+            items.push(sorted);
+        }
+
+        HashIndex { hashes, offsets, items }
+    }
+
+    /// Every item within `radius` bits of `hash`, with its distance, in
+    /// item order.
+    pub fn find_within(&self, hash: u64, radius: u32) -> Vec<(usize, u32)> {
+        let flips = masks_within(radius / CHUNKS as u32);
+        let mut candidates = Vec::new();
+        for chunk in 0..CHUNKS {
+            let value = substring(hash, chunk);
+            for &flip in &flips {
+                let bucket = (value ^ flip) as usize;
+                let (start, end) = (self.offsets[chunk][bucket], self.offsets[chunk][bucket + 1]);
+                candidates.extend_from_slice(&self.items[chunk][start as usize..end as usize]);
+            }
+        }
+
+        // A close match sits in several chunks' buckets
+        candidates.sort_unstable();
+        candidates.dedup();
+        candidates
+            .into_iter()
+            .map(|i| (i as usize, hamming(hash, self.hashes[i as usize])))
+            .filter(|&(_, distance)| distance <= radius)
+            .collect()
+    }
+}
+
+#[inline(always)]
+pub fn hamming(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+fn substring(hash: u64, chunk: usize) -> u16 {
+    (hash >> (chunk as u32 * CHUNK_BITS)) as u16
+}
+
+// Every 16-bit mask with at most `bits` bits set. Each level extends the
+// previous one by a bit above its highest, so no mask is produced twice.
+fn masks_within(bits: u32) -> Vec<u16> {
+    let mut masks = vec![0u16];
+    let mut level = vec![0u16];
+    for _ in 0..bits.min(CHUNK_BITS) {
+        level = level
+            .iter()
+            .flat_map(|&mask| {
+                let lowest_free = CHUNK_BITS - mask.leading_zeros();
+                (lowest_free..CHUNK_BITS).map(move |bit| mask | (1 << bit))
+            })
+            .collect();
+        masks.extend_from_slice(&level);
+    }
+    masks
+}
@@ -1,13 +1,27 @@
 use crate::modules::config::get_config;
+use crate::modules::animation;
+use crate::modules::db::connect_db;
+use crate::modules::filecache::content_hash;
+use crate::modules::hashindex::{hamming, HashIndex};
+use crate::modules::mediatype::{self, MediaKind};
+use crate::{log_error, log_info};
+use dashmap::mapref::entry::Entry;
 use dashmap::DashMap;
-use image::imageops;
+use image::{imageops, DynamicImage};
 use once_cell::sync::Lazy;
 use rayon::prelude::*;
-use serde::Serialize;
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
 use std::f64::consts::PI;
+use std::fs::File;
+use std::hash::Hash;
+use std::io::{self, Read};
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::UNIX_EPOCH;
 use tauri::Emitter;
 use tokio::task;
 
@@ -15,21 +29,28 @@ use tokio::task;
 
 const HASH_RESIZE: u32 = 32;
 const DCT_SIZE: usize = 8; // top-left DCT coefficients to use
+const PHASH_FRAMES: usize = 64; // frames averaged for animated images
+const PARTIAL_HASH_LEN: u64 = 64 * 1024; // bytes hashed to split same-size files
 
 const PHASE_INIT: &str = "Initializing";
 const PHASE_COLLECTING: &str = "Collecting Images";
+const PHASE_EXACT: &str = "Finding Exact Copies";
 const PHASE_HASHING: &str = "Computing Perceptual Hashes";
 const PHASE_COMPARING: &str = "Comparing Images";
 const PHASE_FINALIZING: &str = "Finalizing Results";
 const PHASE_COMPLETE: &str = "Complete";
+const PHASE_CANCELLED: &str = "Cancelled";
 
 // ── types ─────────────────────────────────────────────────────────────────────
 
+/// One group of duplicates. `exact` groups are byte-identical copies;
+/// the rest matched on their perceptual hash.
 #[derive(Debug, Serialize, Clone)]
 pub struct DuplicateImage {
     pub path: String,
     pub category: String,
     pub similarity: f64,
+    pub exact: bool,
     pub duplicates: Vec<DuplicateMatch>,
 }
 
@@ -38,10 +59,12 @@ pub struct DuplicateMatch {
     pub path: String,
     pub category: String,
     pub similarity: f64,
+    pub exact: bool,
 }
 
 #[derive(Debug, Serialize, Clone)]
 pub struct ProgressInfo {
+    pub scan_id: String,
     pub filename: String,
     pub progress: f32,
     pub status: String,
@@ -54,18 +77,64 @@ pub struct ProgressInfo {
     pub elapsed_time: Option<String>,
 }
 
+/// Which files a scan covers. `Library` and `Categories` compare their
+/// files among themselves. `Files` and `NewSinceLastScan` compare their
+/// files against the whole library and only report groups that contain at
+/// least one of them.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ScanScope {
+    #[default]
+    Library,
+    Categories { categories: Vec<String> },
+    Files { paths: Vec<String> },
+    /// Files no finished scan has covered since they were added or edited.
+    NewSinceLastScan,
+}
+
+#[derive(Clone)]
+struct ImageFile {
+    path: PathBuf,
+    category: String,
+    size: u64,
+    modified_ms: i64,
+    in_scope: bool,
+}
+
 #[derive(Clone)]
 struct ImageHash {
     path: PathBuf,
     category: String,
     hash: u64,
     file_size: u64,
+    modified_ms: i64,
+    in_scope: bool,
+}
+
+// Per-scan state, so concurrent scans never share progress or cancellation.
+#[derive(Default)]
+struct DupeScan {
+    cancelled: AtomicBool,
+    compared: AtomicUsize,
+}
+
+impl DupeScan {
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    fn check(&self) -> Result<(), String> {
+        if self.is_cancelled() {
+            return Err("cancelled".to_string());
+        }
+        Ok(())
+    }
 }
 
 // ── global state ──────────────────────────────────────────────────────────────
 
-static PROCESSED_SET: Lazy<DashMap<PathBuf, bool>> = Lazy::new(DashMap::new);
-static PROGRESS_COUNTER: AtomicUsize = AtomicUsize::new(0);
+// Running scans by the id their caller chose
+static DUPE_SCANS: Lazy<DashMap<String, Arc<DupeScan>>> = Lazy::new(DashMap::new);
 
 // Pre-computed DCT cosine table: cos_table[k][n] = cos(π/32 * (n+0.5) * k)
 static DCT_COS: Lazy<[[f64; 32]; 8]> = Lazy::new(|| {
@@ -99,19 +168,11 @@ fn dct1d_partial(row: &[f64]) -> [f64; DCT_SIZE] {
 }
 
 fn compute_phash(path: &Path) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
-    let img = image::open(path)?.into_luma8();
-    let small = imageops::resize(&img, HASH_RESIZE, HASH_RESIZE, imageops::FilterType::Lanczos3);
-
-    // Build float rows
-    let rows: Vec<[f64; 32]> = (0..32)
-        .map(|y| {
-            let mut row = [0.0f64; 32];
-            for x in 0..32usize {
-                row[x] = small.get_pixel(x as u32, y as u32)[0] as f64;
-            }
-            row
-        })
-        .collect();
+    let rows = if mediatype::detect(path).kind == MediaKind::AnimatedImage {
+        animated_luma_rows(path)?
+    } else {
+        luma_rows(&mediatype::open_image(path)?)
+    };
 
     // Row DCT: rows[y] → dct_rows[y][0..8]
     let dct_rows: Vec<[f64; DCT_SIZE]> = rows.iter().map(|r| dct1d_partial(r)).collect();
@@ -140,10 +201,218 @@ fn compute_phash(path: &Path) -> Result<u64, Box<dyn std::error::Error + Send +
     Ok(hash)
 }
 
+// 32×32 luma samples as float rows.
+fn luma_rows(img: &DynamicImage) -> Vec<[f64; 32]> {
+    let small = imageops::resize(&img.to_luma8(), HASH_RESIZE, HASH_RESIZE, imageops::FilterType::Lanczos3);
+    (0..32)
+        .map(|y| std::array::from_fn(|x| small.get_pixel(x as u32, y)[0] as f64))
+        .collect()
+}
+
+// Averages the samples of up to PHASH_FRAMES frames, so two animations
+// match on their overall content rather than on their first frame.
+fn animated_luma_rows(path: &Path) -> Result<Vec<[f64; 32]>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut sum = vec![[0.0f64; 32]; 32];
+    let mut count = 0;
+    for frame in animation::open_frames(path)?.take(PHASH_FRAMES) {
+        let rows = luma_rows(&DynamicImage::ImageRgba8(frame?.into_buffer()));
+        for (acc, row) in sum.iter_mut().zip(rows) {
+            for (a, v) in acc.iter_mut().zip(row) {
+                *a += v;
+            }
+        }
+        count += 1;
+    }
+    if count == 0 {
+        return Err(format!("Animation has no frames: {}", path.display()).into());
+    }
+    for row in &mut sum {
+        for v in row.iter_mut() {
+            *v /= count as f64;
+        }
+    }
+    Ok(sum)
+}
+
 // Hamming distance → [0,1] similarity
 #[inline(always)]
 fn hamming_similarity(a: u64, b: u64) -> f64 {
-    1.0 - ((a ^ b).count_ones() as f64 / 64.0)
+    1.0 - (hamming(a, b) as f64 / 64.0)
+}
+
+// Largest Hamming distance that still meets `threshold`.
+fn radius_for(threshold: f64) -> u32 {
+    // The epsilon keeps thresholds like 0.875 (exactly 8 bits) from rounding down
+    ((1.0 - threshold.clamp(0.0, 1.0)) * 64.0 + 1e-9).floor() as u32
+}
+
+// ── exact copies ──────────────────────────────────────────────────────────────
+//
+// Byte-identical files are found without decoding anything: only files of
+// the same size can match, of those only the ones whose first
+// PARTIAL_HASH_LEN bytes agree, and the full SHA-256 (taken from the index
+// while it is current) settles it.
+
+// Groups of indices into `files` with identical contents, each in file order.
+fn find_exact_groups(files: &[ImageFile], scan: &DupeScan) -> Vec<Vec<usize>> {
+    let same_size = split_by((0..files.len()).collect(), |i| Some(files[i].size));
+
+    let mut groups: Vec<Vec<usize>> = same_size
+        .into_par_iter()
+        .filter(|_| !scan.is_cancelled())
+        .flat_map_iter(|group| {
+            split_by(group, |i| partial_hash(&files[i].path).ok())
+                .into_iter()
+                .flat_map(|group| {
+                    split_by(group, |i| {
+                        let path = &files[i].path;
+                        std::fs::metadata(path).and_then(|m| content_hash(path, &m)).ok()
+                    })
+                })
+        })
+        .collect();
+
+    for group in &mut groups {
+        group.sort_unstable();
+    }
+    groups.sort_unstable_by_key(|group| group[0]);
+    groups
+}
+
+// Buckets `items` by `key`, keeping buckets of two or more. Items without a
+// key (unreadable files) drop out.
+fn split_by<K: Eq + Hash>(items: Vec<usize>, key: impl Fn(usize) -> Option<K>) -> Vec<Vec<usize>> {
+    let mut buckets: HashMap<K, Vec<usize>> = HashMap::new();
+    for i in items {
+        if let Some(k) = key(i) {
+            buckets.entry(k).or_default().push(i);
+        }
+    }
+    buckets.into_values().filter(|bucket| bucket.len() > 1).collect()
+}
+
+fn partial_hash(path: &Path) -> io::Result<[u8; 32]> {
+    let mut head = Vec::new();
+    File::open(path)?.take(PARTIAL_HASH_LEN).read_to_end(&mut head)?;
+    Ok(Sha256::digest(&head).into())
+}
+
+// ── stored hashes ─────────────────────────────────────────────────────────────
+//
+// pHashes are kept in the `phash` column of `images`, which the indexer
+// clears whenever a file's size or mtime changes, so a scan only decodes
+// files that are new or edited since the last one. Files the index hasn't
+// reached yet are hashed every time until it does.
+
+struct StoredHash {
+    category: String,
+    size: i64,
+    modified_ms: i64,
+    hash: u64,
+}
+
+type StoredHashes = HashMap<PathBuf, StoredHash>;
+
+// Every indexed file that has a pHash, by path.
+fn load_stored_hashes() -> Result<StoredHashes, String> {
+    let conn = connect_db()?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT relative_path, filename, category, size, modified_ms, phash FROM images
+             WHERE phash IS NOT NULL",
+        )
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map([], |row| {
+            let path = Path::new(&row.get::<_, String>(0)?).join(row.get::<_, String>(1)?);
+            Ok((path, StoredHash {
+                category: row.get(2)?,
+                size: row.get(3)?,
+                modified_ms: row.get(4)?,
+                hash: row.get::<_, i64>(5)? as u64,
+            }))
+        })
+        .map_err(|e| e.to_string())?;
+    rows.collect::<Result<_, _>>().map_err(|e| e.to_string())
+}
+
+// Indexed decodable images without a pHash.
+fn count_unhashed() -> Result<usize, String> {
+    let conn = connect_db()?;
+    conn.query_row(
+        "SELECT COUNT(*) FROM images
+         WHERE phash IS NULL AND media_kind IN ('image', 'animated_image')",
+        [],
+        |row| row.get::<_, i64>(0),
+    )
+    .map(|count| count as usize)
+    .map_err(|e| e.to_string())
+}
+
+// Rows whose size or mtime moved on since hashing are left alone.
+fn store_hashes(hashes: &[ImageHash]) -> Result<(), String> {
+    let mut conn = connect_db()?;
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    {
+        let mut stmt = tx
+            .prepare(
+                "UPDATE images SET phash = ?1
+                 WHERE relative_path = ?2 AND filename = ?3 AND size = ?4 AND modified_ms = ?5",
+            )
+            .map_err(|e| e.to_string())?;
+        for h in hashes {
+            let dir = h.path.parent().map(|p| p.to_string_lossy().to_string()).unwrap_or_default();
+            let file_name = h.path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+            stmt.execute(params![h.hash as i64, dir, file_name, h.file_size as i64, h.modified_ms])
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    tx.commit().map_err(|e| e.to_string())
+}
+
+// ── scan markers ──────────────────────────────────────────────────────────────
+//
+// `dupe_checked_ms` holds the mtime a file had when a finished scan last
+// compared it against the whole library, which is what a "new since last
+// scan" scope compares against. Category scans leave it alone.
+// Edited files no longer match it, and moved or not yet indexed files have
+// no marker, so all of those count as new.
+
+fn load_checked() -> Result<HashMap<PathBuf, i64>, String> {
+    let conn = connect_db()?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT relative_path, filename, dupe_checked_ms FROM images
+             WHERE dupe_checked_ms IS NOT NULL",
+        )
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map([], |row| {
+            let path = Path::new(&row.get::<_, String>(0)?).join(row.get::<_, String>(1)?);
+            Ok((path, row.get(2)?))
+        })
+        .map_err(|e| e.to_string())?;
+    rows.collect::<Result<_, _>>().map_err(|e| e.to_string())
+}
+
+fn mark_checked(files: &[ImageFile]) -> Result<(), String> {
+    let mut conn = connect_db()?;
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    {
+        let mut stmt = tx
+            .prepare(
+                "UPDATE images SET dupe_checked_ms = ?4
+                 WHERE relative_path = ?1 AND filename = ?2 AND size = ?3 AND modified_ms = ?4",
+            )
+            .map_err(|e| e.to_string())?;
+        for file in files {
+            let dir = file.path.parent().map(|p| p.to_string_lossy().to_string()).unwrap_or_default();
+            let file_name = file.path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+            stmt.execute(params![dir, file_name, file.size as i64, file.modified_ms])
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    tx.commit().map_err(|e| e.to_string())
 }
 
 // ── helpers ───────────────────────────────────────────────────────────────────
@@ -158,8 +427,17 @@ fn format_time(secs: f64) -> String {
     }
 }
 
-fn emit_progress(win: &Arc<tauri::Window>, phase: &str, progress: f32, current: usize, total: usize, start: std::time::Instant) {
+fn emit_progress(
+    win: &Arc<tauri::Window>,
+    scan_id: &str,
+    phase: &str,
+    progress: f32,
+    current: usize,
+    total: usize,
+    start: std::time::Instant,
+) {
     let _ = win.emit("dupe-check-progress", ProgressInfo {
+        scan_id: scan_id.to_string(),
         filename: "Duplicate Check".to_string(),
         progress,
         status: "processing".to_string(),
@@ -173,7 +451,8 @@ fn emit_progress(win: &Arc<tauri::Window>, phase: &str, progress: f32, current:
     });
 }
 
-fn collect_image_paths(root: &Path) -> Result<Vec<(PathBuf, String, u64)>, String> {
+// Image files of every category, or only of `categories` if given.
+fn collect_image_paths(root: &Path, categories: Option<&[String]>) -> Result<Vec<ImageFile>, String> {
     if !root.exists() {
         return Err(format!("Root path does not exist: {}", root.display()));
     }
@@ -189,18 +468,21 @@ fn collect_image_paths(root: &Path) -> Result<Vec<(PathBuf, String, u64)>, Strin
             if !dir.is_dir() { return None; }
             let cat = dir.file_name()?.to_str()?.to_string();
             if cat == "temp" { return None; }
+            if categories.is_some_and(|wanted| !wanted.contains(&cat)) { return None; }
 
             let files = std::fs::read_dir(&dir).ok()?
                 .collect::<Result<Vec<_>, _>>().ok()?;
 
             let paths: Vec<_> = files.iter().filter_map(|f| {
                 let p = f.path();
-                let ext = p.extension()?.to_str()?.to_lowercase();
-                if ["jpg", "jpeg", "png", "jfif", "webp", "bmp", "tiff", "gif"]
-                    .contains(&ext.as_str())
-                {
-                    let size = std::fs::metadata(&p).map(|m| m.len()).unwrap_or(0);
-                    Some((p, cat.clone(), size))
+                if p.is_file() && mediatype::detect(&p).is_decodable() {
+                    let metadata = std::fs::metadata(&p).ok()?;
+                    let modified_ms = metadata
+                        .modified()
+                        .ok()
+                        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                        .map_or(0, |d| d.as_millis() as i64);
+                    Some(ImageFile { path: p, category: cat.clone(), size: metadata.len(), modified_ms, in_scope: true })
                 } else {
                     None
                 }
@@ -212,21 +494,33 @@ fn collect_image_paths(root: &Path) -> Result<Vec<(PathBuf, String, u64)>, Strin
         .collect())
 }
 
-// ── command ───────────────────────────────────────────────────────────────────
+// ── commands ──────────────────────────────────────────────────────────────────
 
+/// Finds exact and near duplicates within `scope` (the whole library by
+/// default). `scan_id` names the scan in its progress events and is what
+/// `cancel_duplicate_scan` takes; a cancelled scan fails with "cancelled".
 #[tauri::command]
 pub async fn find_duplicates(
+    scan_id: String,
     similarity_threshold: Option<f64>,
+    scope: Option<ScanScope>,
     window: tauri::Window,
 ) -> Result<Vec<DuplicateImage>, String> {
     let threshold = similarity_threshold.unwrap_or(0.90);
+    let scope = scope.unwrap_or_default();
     let root_path = get_config().folderPath;
     let start = std::time::Instant::now();
 
-    PROCESSED_SET.clear();
-    PROGRESS_COUNTER.store(0, Ordering::Relaxed);
+    let scan = Arc::new(DupeScan::default());
+    match DUPE_SCANS.entry(scan_id.clone()) {
+        Entry::Occupied(_) => return Err(format!("Duplicate scan '{}' is already running", scan_id)),
+        Entry::Vacant(slot) => {
+            slot.insert(scan.clone());
+        }
+    }
 
     let _ = window.emit("dupe-check-started", ProgressInfo {
+        scan_id: scan_id.clone(),
         filename: "Duplicate Check".to_string(),
         progress: 0.0,
         status: "starting".to_string(),
@@ -237,41 +531,144 @@ pub async fn find_duplicates(
         elapsed_time: Some("0s".to_string()),
     });
 
-    task::spawn_blocking(move || {
+    let id = scan_id.clone();
+    let events = window.clone();
+    let result = task::spawn_blocking(move || {
         let window = Arc::new(window);
 
         // ── Phase 1: collect paths ────────────────────────────────────────────
-        emit_progress(&window, PHASE_COLLECTING, 5.0, 0, 0, start);
-
-        let image_paths = collect_image_paths(&root_path)
+        //
+        // Narrow scopes still collect the whole library, since their files
+        // are compared against all of it.
+        emit_progress(&window, &id, PHASE_COLLECTING, 5.0, 0, 0, start);
+
+        let categories = match &scope {
+            ScanScope::Categories { categories } => Some(categories.as_slice()),
+            _ => None,
+        };
+        let mut image_paths = collect_image_paths(&root_path, categories)
             .map_err(|e| format!("Failed to collect image paths: {}", e))?;
+
+        match &scope {
+            ScanScope::Library | ScanScope::Categories { .. } => {}
+            ScanScope::Files { paths } => {
+                let wanted: HashSet<PathBuf> = paths.iter().map(PathBuf::from).collect();
+                for file in &mut image_paths {
+                    file.in_scope = wanted.contains(&file.path);
+                }
+            }
+            ScanScope::NewSinceLastScan => {
+                let checked = load_checked()?;
+                for file in &mut image_paths {
+                    file.in_scope = checked.get(&file.path) != Some(&file.modified_ms);
+                }
+            }
+        }
+        // A category scan never compared its files with the rest of the
+        // library, so it doesn't count as having checked them
+        let covered: Vec<ImageFile> = match &scope {
+            ScanScope::Categories { .. } => Vec::new(),
+            _ => image_paths.iter().filter(|file| file.in_scope).cloned().collect(),
+        };
+        scan.check()?;
+
+        // ── Phase 2: exact copies ─────────────────────────────────────────────
+        //
+        // Each group's first file stands in for the group in the perceptual
+        // pass; the other copies would only match it at 100%. It inherits
+        // the group's scope, so near duplicates of an in-scope copy are
+        // still looked for.
+        emit_progress(&window, &id, PHASE_EXACT, 7.0, 0, image_paths.len(), start);
+
+        let exact_groups = find_exact_groups(&image_paths, &scan);
+        scan.check()?;
+        let as_match = |file: &ImageFile| DuplicateMatch {
+            path: file.path.to_string_lossy().to_string(),
+            category: file.category.clone(),
+            similarity: 1.0,
+            exact: true,
+        };
+        let mut results: Vec<DuplicateImage> = exact_groups
+            .iter()
+            .filter(|group| group.iter().any(|&i| image_paths[i].in_scope))
+            .map(|group| DuplicateImage {
+                path: image_paths[group[0]].path.to_string_lossy().to_string(),
+                category: image_paths[group[0]].category.clone(),
+                similarity: 1.0,
+                exact: true,
+                duplicates: group[1..].iter().map(|&i| as_match(&image_paths[i])).collect(),
+            })
+            .collect();
+
+        for group in &exact_groups {
+            image_paths[group[0]].in_scope = group.iter().any(|&i| image_paths[i].in_scope);
+        }
+        let copies: HashSet<usize> = exact_groups.iter().flat_map(|group| group[1..].iter().copied()).collect();
+        let copy_files: Vec<(ImageFile, PathBuf)> = exact_groups
+            .iter()
+            .flat_map(|group| group[1..].iter().map(|&i| (image_paths[i].clone(), image_paths[group[0]].path.clone())))
+            .collect();
+        let image_paths: Vec<ImageFile> = image_paths
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| !copies.contains(i))
+            .map(|(_, file)| file)
+            .collect();
         let total = image_paths.len();
 
-        emit_progress(&window, PHASE_HASHING, 10.0, 0, total, start);
+        emit_progress(&window, &id, PHASE_HASHING, 10.0, 0, total, start);
+
+        // ── Phase 3: hash new and changed images in parallel ──────────────────
+        let stored = load_stored_hashes().unwrap_or_else(|e| {
+            log_error!("Failed to load stored perceptual hashes: {}", e);
+            StoredHashes::new()
+        });
+        let mut image_hashes = Vec::with_capacity(total);
+        let mut pending = Vec::new();
+        for file in image_paths {
+            match stored.get(&file.path) {
+                Some(stored) if stored.size == file.size as i64 && stored.modified_ms == file.modified_ms => {
+                    image_hashes.push(ImageHash {
+                        path: file.path,
+                        category: file.category,
+                        hash: stored.hash,
+                        file_size: file.size,
+                        modified_ms: file.modified_ms,
+                        in_scope: file.in_scope,
+                    });
+                }
+                _ => pending.push(file),
+            }
+        }
+        let reused = image_hashes.len();
 
-        // ── Phase 2: hash all images in parallel ──────────────────────────────
-        let hashed = Arc::new(AtomicUsize::new(0));
+        let hashed = Arc::new(AtomicUsize::new(reused));
         let hash_start = std::time::Instant::now();
         let win2 = window.clone();
 
-        let image_hashes: Vec<ImageHash> = image_paths
+        let fresh: Vec<ImageHash> = pending
             .par_iter()
-            .filter_map(|(path, category, file_size)| {
-                match compute_phash(path) {
+            .filter_map(|file| {
+                if scan.is_cancelled() {
+                    return None;
+                }
+                match compute_phash(&file.path) {
                     Ok(hash) => {
                         let n = hashed.fetch_add(1, Ordering::Relaxed);
+                        let done = n - reused;
                         // Throttle progress events
-                        if n % 20 == 0 || n == total.saturating_sub(1) {
+                        if done % 20 == 0 || n == total.saturating_sub(1) {
                             let elapsed = hash_start.elapsed().as_secs_f64();
-                            let eta = (n > 5).then(||
-                                format_time((elapsed / n as f64) * (total - n) as f64)
+                            let eta = (done > 5).then(||
+                                format_time((elapsed / done as f64) * (total - n) as f64)
                             );
                             let _ = win2.emit("dupe-check-progress", ProgressInfo {
+                                scan_id: id.clone(),
                                 filename: "Duplicate Check".to_string(),
                                 progress: 10.0 + (n as f32 / total as f32) * 40.0,
                                 status: "processing".to_string(),
                                 phase: PHASE_HASHING.to_string(),
-                                current_file: Some(path.file_name()
+                                current_file: Some(file.path.file_name()
                                     .map(|n| n.to_string_lossy().to_string())
                                     .unwrap_or_default()),
                                 target_file: None,
@@ -282,10 +679,12 @@ pub async fn find_duplicates(
                             });
                         }
                         Some(ImageHash {
-                            path: path.clone(),
-                            category: category.clone(),
+                            path: file.path.clone(),
+                            category: file.category.clone(),
                             hash,
-                            file_size: *file_size,
+                            file_size: file.size,
+                            modified_ms: file.modified_ms,
+                            in_scope: file.in_scope,
                         })
                     }
                     Err(_) => None,
@@ -293,88 +692,136 @@ pub async fn find_duplicates(
             })
             .collect();
 
-        let n = image_hashes.len();
-        let total_pairs = n * n.saturating_sub(1) / 2;
+        // Hashes finished before a cancel are kept for the next scan
+        if let Err(e) = store_hashes(&fresh) {
+            log_error!("Failed to store perceptual hashes: {}", e);
+        }
+        scan.check()?;
+        log_info!("Duplicate scan {}: {} stored perceptual hashes, {} computed", id, reused, fresh.len());
+        image_hashes.extend(fresh);
+
+        // Copies share their first file's pHash; storing it lets single-image
+        // queries find them as well
+        let by_path: HashMap<&Path, u64> = image_hashes.iter().map(|img| (img.path.as_path(), img.hash)).collect();
+        let copy_hashes: Vec<ImageHash> = copy_files
+            .into_iter()
+            .filter_map(|(file, original)| {
+                let hash = *by_path.get(original.as_path())?;
+                let current = stored.get(&file.path).is_some_and(|s| {
+                    s.hash == hash && s.size == file.size as i64 && s.modified_ms == file.modified_ms
+                });
+                (!current).then_some(ImageHash {
+                    path: file.path,
+                    category: file.category,
+                    hash,
+                    file_size: file.size,
+                    modified_ms: file.modified_ms,
+                    in_scope: file.in_scope,
+                })
+            })
+            .collect();
+        if let Err(e) = store_hashes(&copy_hashes) {
+            log_error!("Failed to store perceptual hashes of exact copies: {}", e);
+        }
 
-        emit_progress(&window, PHASE_COMPARING, 50.0, 0, total_pairs, start);
+        let n = image_hashes.len();
 
-        // ── Phase 3: batch pairwise comparison ────────────────────────────────
+        // ── Phase 4: radius queries against a multi-index hash ────────────────
         //
-        // Outer loop parallelised with Rayon — each thread owns a slice of `i`
-        // indices and scans all j > i within that slice.  No manual core
-        // splitting needed; Rayon's work-stealer balances the load.
+        // Each in-scope image asks the index for everything within the
+        // threshold's Hamming radius, so the pass costs a few bucket lookups
+        // per image rather than one comparison per pair. Queries run in
+        // parallel; the index is read-only once built. A pair of in-scope
+        // images is reported by the earlier one only, while out-of-scope
+        // images never query and can join any group.
         let cmp_start = std::time::Instant::now();
         let win3 = window.clone();
         let hashes = Arc::new(image_hashes);
         let h = &*hashes;
+        let index = HashIndex::new(h.iter().map(|img| img.hash).collect());
+        let radius = radius_for(threshold);
+        let queries: Vec<usize> = (0..n).filter(|&i| h[i].in_scope).collect();
+        let q = queries.len();
 
-        let results: Vec<DuplicateImage> = (0..n)
-            .into_par_iter()
-            .filter_map(|i| {
-                if PROCESSED_SET.contains_key(&h[i].path) {
-                    return None;
-                }
+        emit_progress(&window, &id, PHASE_COMPARING, 50.0, 0, q, start);
 
-                let matches: Vec<DuplicateMatch> = (i + 1..n)
-                    .filter_map(|j| {
-                        if PROCESSED_SET.contains_key(&h[j].path) {
-                            return None;
-                        }
-
-                        // Fast size pre-filter: skip pairs with very different file sizes
-                        let size_ratio = h[i].file_size.min(h[j].file_size) as f64
-                            / h[i].file_size.max(h[j].file_size).max(1) as f64;
-                        if size_ratio < 0.2 {
-                            return None;
-                        }
-
-                        let g = PROGRESS_COUNTER.fetch_add(1, Ordering::Relaxed);
-                        if g % 10_000 == 0 && g > 0 {
-                            let elapsed = cmp_start.elapsed().as_secs_f64();
-                            let eta = format_time((elapsed / g as f64) * (total_pairs - g) as f64);
-                            let _ = win3.emit("dupe-check-progress", ProgressInfo {
-                                filename: "Duplicate Check".to_string(),
-                                progress: (50.0 + (g as f32 / total_pairs as f32) * 45.0).min(95.0),
-                                status: "processing".to_string(),
-                                phase: PHASE_COMPARING.to_string(),
-                                current_file: None,
-                                target_file: None,
-                                processed_files: g,
-                                total_files: total_pairs,
-                                estimated_time_remaining: Some(eta),
-                                elapsed_time: Some(format_time(start.elapsed().as_secs_f64())),
-                            });
-                        }
-
-                        let sim = hamming_similarity(h[i].hash, h[j].hash);
-                        if sim >= threshold {
-                            PROCESSED_SET.insert(h[j].path.clone(), true);
-                            Some(DuplicateMatch {
-                                path: h[j].path.to_string_lossy().to_string(),
-                                category: h[j].category.clone(),
-                                similarity: sim,
-                            })
-                        } else {
-                            None
-                        }
-                    })
-                    .collect();
-
-                if !matches.is_empty() {
-                    Some(DuplicateImage {
-                        path: h[i].path.to_string_lossy().to_string(),
-                        category: h[i].category.clone(),
-                        similarity: 1.0,
-                        duplicates: matches,
-                    })
-                } else {
-                    None
+        let neighbours: Vec<Vec<usize>> = queries
+            .par_iter()
+            .map(|&i| {
+                if scan.is_cancelled() {
+                    return Vec::new();
                 }
+                let g = scan.compared.fetch_add(1, Ordering::Relaxed);
+                if g % 500 == 0 && g > 0 {
+                    let elapsed = cmp_start.elapsed().as_secs_f64();
+                    let eta = format_time((elapsed / g as f64) * q.saturating_sub(g) as f64);
+                    let _ = win3.emit("dupe-check-progress", ProgressInfo {
+                        scan_id: id.clone(),
+                        filename: "Duplicate Check".to_string(),
+                        progress: (50.0 + (g as f32 / q as f32) * 45.0).min(95.0),
+                        status: "processing".to_string(),
+                        phase: PHASE_COMPARING.to_string(),
+                        current_file: None,
+                        target_file: None,
+                        processed_files: g,
+                        total_files: q,
+                        estimated_time_remaining: Some(eta),
+                        elapsed_time: Some(format_time(start.elapsed().as_secs_f64())),
+                    });
+                }
+
+                index
+                    .find_within(h[i].hash, radius)
+                    .into_iter()
+                    .map(|(j, _)| j)
+                    .filter(|&j| j > i || (j < i && !h[j].in_scope))
+                    .collect()
             })
             .collect();
+        scan.check()?;
+
+        // Grouping runs in index order on one thread, so every image lands
+        // in at most one group and the result doesn't depend on scheduling.
+        let mut claimed = vec![false; n];
+        let mut similar = Vec::new();
+        for (&i, found) in queries.iter().zip(neighbours) {
+            if claimed[i] {
+                continue;
+            }
+            let matches: Vec<DuplicateMatch> = found
+                .into_iter()
+                .filter_map(|j| {
+                    if std::mem::replace(&mut claimed[j], true) {
+                        return None;
+                    }
+                    Some(DuplicateMatch {
+                        path: h[j].path.to_string_lossy().to_string(),
+                        category: h[j].category.clone(),
+                        similarity: hamming_similarity(h[i].hash, h[j].hash),
+                        exact: false,
+                    })
+                })
+                .collect();
+
+            if !matches.is_empty() {
+                similar.push(DuplicateImage {
+                    path: h[i].path.to_string_lossy().to_string(),
+                    category: h[i].category.clone(),
+                    similarity: 1.0,
+                    exact: false,
+                    duplicates: matches,
+                });
+            }
+        }
+        results.extend(similar);
+
+        if let Err(e) = mark_checked(&covered) {
+            log_error!("Failed to mark scanned files: {}", e);
+        }
 
         // ── Done ─────────────────────────────────────────────────────────────
         let _ = window.emit("dupe-check-finished", ProgressInfo {
+            scan_id: id.clone(),
             filename: "Duplicate Check".to_string(),
             progress: 100.0,
             status: "complete".to_string(),
@@ -389,5 +836,82 @@ pub async fn find_duplicates(
         Ok(results)
     })
     .await
+    .map_err(|e| e.to_string());
+    DUPE_SCANS.remove(&scan_id);
+
+    let result = result?;
+    if matches!(&result, Err(e) if e == "cancelled") {
+        log_info!("Duplicate scan {} cancelled", scan_id);
+        let _ = events.emit("dupe-check-cancelled", ProgressInfo {
+            scan_id,
+            filename: "Duplicate Check".to_string(),
+            progress: 0.0,
+            status: "cancelled".to_string(),
+            phase: PHASE_CANCELLED.to_string(),
+            current_file: None, target_file: None,
+            processed_files: 0, total_files: 0,
+            estimated_time_remaining: None,
+            elapsed_time: Some(format_time(start.elapsed().as_secs_f64())),
+        });
+    }
+    result
+}
+
+#[tauri::command]
+pub async fn cancel_duplicate_scan(scan_id: String) -> Result<(), String> {
+    if let Some(scan) = DUPE_SCANS.get(&scan_id) {
+        scan.cancelled.store(true, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+/// Images within the threshold of one image, plus how many indexed images
+/// had no stored pHash to compare against. Those are covered once a
+/// duplicate scan has hashed them.
+#[derive(Debug, Serialize, Clone)]
+pub struct SimilarImages {
+    pub matches: Vec<DuplicateMatch>,
+    pub unhashed: usize,
+}
+
+/// Radius query for one image: every hashed library image within the
+/// threshold, most similar first. The query image itself is hashed on the
+/// spot if it has no stored pHash.
+#[tauri::command]
+pub async fn find_similar_images(
+    path: String,
+    similarity_threshold: Option<f64>,
+) -> Result<SimilarImages, String> {
+    let threshold = similarity_threshold.unwrap_or(0.90);
+
+    task::spawn_blocking(move || {
+        let path = PathBuf::from(path);
+        let stored = load_stored_hashes()?;
+        let hash = match stored.get(&path) {
+            Some(entry) => entry.hash,
+            None => compute_phash(&path).map_err(|e| format!("Failed to hash {}: {}", path.display(), e))?,
+        };
+
+        // One query doesn't pay back building a multi-index; a linear pass
+        // over the hashes already in memory is cheaper
+        let radius = radius_for(threshold);
+        let mut matches: Vec<DuplicateMatch> = stored
+            .iter()
+            .filter(|(p, _)| **p != path)
+            .filter_map(|(p, entry)| {
+                let distance = hamming(hash, entry.hash);
+                (distance <= radius).then(|| DuplicateMatch {
+                    path: p.to_string_lossy().to_string(),
+                    category: entry.category.clone(),
+                    similarity: 1.0 - distance as f64 / 64.0,
+                    exact: false,
+                })
+            })
+            .collect();
+        matches.sort_by(|a, b| b.similarity.total_cmp(&a.similarity).then_with(|| a.path.cmp(&b.path)));
+
+        Ok(SimilarImages { matches, unhashed: count_unhashed()? })
+    })
+    .await
     .map_err(|e| e.to_string())?
 }
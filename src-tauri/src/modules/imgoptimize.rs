@@ -1,37 +1,216 @@
+use crate::modules::animation;
+use crate::modules::codecs;
+use crate::modules::config::{get_config, ThumbProfile};
+use crate::modules::mediatype::{self, DecodeSource, DecodeTarget, DecodedImage, MediaKind};
+use crate::modules::thumbcache::{self, Background, FitMode, StudyView, ThumbFormat, ThumbKey, ThumbSpec, Tone};
+use crate::log_info;
 use base64::{engine::general_purpose, Engine as _};
-use dashmap::DashMap;
 use fast_image_resize as fr;
-use image::{codecs::jpeg::JpegEncoder, DynamicImage, RgbImage};
+use image::{
+    codecs::{jpeg::JpegEncoder, png::PngEncoder, webp::WebPEncoder},
+    imageops, DynamicImage, GenericImageView, ImageBuffer, Pixel, Rgb, RgbImage, RgbaImage,
+};
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::hash_map::DefaultHasher,
-    hash::{Hash, Hasher},
+    collections::{BTreeMap, HashMap},
     io::Cursor,
     path::Path,
-    sync::Arc,
-    time::{Duration, SystemTime, UNIX_EPOCH},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 use tokio::task;
 
+const MB: u64 = 1024 * 1024;
+
+// Checkerboard used to show transparency when flattening
+const CHECKER_CELL: u32 = 8;
+const CHECKER_LIGHT: [u8; 3] = [255, 255, 255];
+const CHECKER_DARK: [u8; 3] = [204, 204, 204];
+
+// Smart crop scores positions on a copy no larger than this
+const SMART_SAMPLE: u32 = 128;
+
+// Animated previews stop after this many frames
+const MAX_ANIMATION_FRAMES: usize = 500;
+
+// A single thumbnail may take at most this share of the budget; anything
+// bigger would flush most of the cache for one entry.
+const MAX_ENTRY_SHARE: u64 = 8;
+
+// Warm layer in front of the on-disk thumbnail cache
 lazy_static::lazy_static! {
-    static ref IMAGE_CACHE: Arc<DashMap<u64, CachedImage>> = Arc::new(DashMap::new());
+    static ref IMAGE_CACHE: Mutex<MemoryCache> =
+        Mutex::new(MemoryCache::new(get_config().thumb_memory_mb * MB));
+    static ref RENDER_STATS: Mutex<RenderStats> = Mutex::new(RenderStats::default());
+    // Decoded sRGB channel value → linear light, for the study views
+    static ref SRGB_TO_LINEAR: [f32; 256] = std::array::from_fn(|v| {
+        let c = v as f32 / 255.0;
+        if c <= 0.040_45 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+    });
 }
 
-#[derive(Clone)]
+// ── memory cache ──────────────────────────────────────────────────────────────
+//
+// Byte-weighted LRU. Each entry carries a tick from a global counter; the
+// `order` map sorts ticks oldest first, so eviction pops from the front
+// until the byte total fits the budget again.
+
 struct CachedImage {
-    data: Vec<u8>,
-    timestamp: u64,
-    access_count: u32,
+    data: Arc<Vec<u8>>,
     path: String,
+    tick: u64,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, Default)]
+pub struct MemoryCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub insertions: u64,
+    pub evictions: u64,
+    pub rejections: u64,
+    pub entries: usize,
+    pub bytes: u64,
+    pub budget_bytes: u64,
+}
+
+struct MemoryCache {
+    entries: HashMap<ThumbKey, CachedImage>,
+    order: BTreeMap<u64, ThumbKey>,
+    next_tick: u64,
+    stats: MemoryCacheStats,
+}
+
+impl MemoryCache {
+    fn new(budget_bytes: u64) -> MemoryCache {
+        MemoryCache {
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            next_tick: 0,
+            stats: MemoryCacheStats { budget_bytes, ..Default::default() },
+        }
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.next_tick += 1;
+        self.next_tick
+    }
+
+    fn get(&mut self, key: &ThumbKey) -> Option<Arc<Vec<u8>>> {
+        let tick = self.tick();
+        let Some(entry) = self.entries.get_mut(key) else {
+            self.stats.misses += 1;
+            return None;
+        };
+        self.order.remove(&entry.tick);
+        entry.tick = tick;
+        self.order.insert(tick, key.clone());
+        self.stats.hits += 1;
+        Some(entry.data.clone())
+    }
+
+    fn insert(&mut self, key: ThumbKey, path: &str, data: Arc<Vec<u8>>) {
+        let size = data.len() as u64;
+        if size > self.stats.budget_bytes / MAX_ENTRY_SHARE {
+            self.stats.rejections += 1;
+            return;
+        }
+
+        self.remove(&key);
+        let tick = self.tick();
+        self.order.insert(tick, key.clone());
+        self.entries.insert(key, CachedImage { data, path: path.to_string(), tick });
+        self.stats.bytes += size;
+        self.stats.insertions += 1;
+        self.evict();
+    }
+
+    fn remove(&mut self, key: &ThumbKey) -> bool {
+        let Some(entry) = self.entries.remove(key) else { return false };
+        self.order.remove(&entry.tick);
+        self.stats.bytes -= entry.data.len() as u64;
+        true
+    }
+
+    fn evict(&mut self) {
+        while self.stats.bytes > self.stats.budget_bytes {
+            let Some((_, key)) = self.order.pop_first() else { break };
+            if let Some(entry) = self.entries.remove(&key) {
+                self.stats.bytes -= entry.data.len() as u64;
+                self.stats.evictions += 1;
+            }
+        }
+    }
+
+    fn retain(&mut self, keep: impl Fn(&ThumbKey, &CachedImage) -> bool) {
+        let dropped: Vec<ThumbKey> = self
+            .entries
+            .iter()
+            .filter(|(key, entry)| !keep(key, entry))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in dropped {
+            self.remove(&key);
+        }
+    }
+
+    fn snapshot(&self) -> MemoryCacheStats {
+        MemoryCacheStats { entries: self.entries.len(), ..self.stats }
+    }
+}
+
+// ── render timings ────────────────────────────────────────────────────────────
+//
+// Totals over every thumbnail rendered since startup (cache hits excluded),
+// split by how the source was decoded so the reduced JPEG paths can be
+// compared against full decodes.
+
+#[derive(Debug, Serialize, Clone, Copy, Default)]
+pub struct RenderStats {
+    pub renders: u64,
+    pub full_decodes: u64,
+    pub scaled_decodes: u64,
+    pub exif_thumbnails: u64,
+    pub fallback_icons: u64,
+    pub decode_ms: f64,
+    pub resize_ms: f64,
+    pub encode_ms: f64,
+}
+
+impl RenderStats {
+    fn record(&mut self, source: DecodeSource, decode: Duration, resize: Duration, encode: Duration) {
+        self.renders += 1;
+        match source {
+            DecodeSource::Full => self.full_decodes += 1,
+            DecodeSource::Scaled(_) => self.scaled_decodes += 1,
+            DecodeSource::ExifThumbnail => self.exif_thumbnails += 1,
+            DecodeSource::FallbackIcon => self.fallback_icons += 1,
+        }
+        self.decode_ms += decode.as_secs_f64() * 1000.0;
+        self.resize_ms += resize.as_secs_f64() * 1000.0;
+        self.encode_ms += encode.as_secs_f64() * 1000.0;
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ThumbnailCacheStats {
+    pub memory: MemoryCacheStats,
+    pub disk: thumbcache::DiskCacheStats,
+    pub render: RenderStats,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct BatchRequest {
     pub paths: Vec<String>,
+    pub profile: Option<String>,
     pub width: Option<i32>,
     pub height: Option<i32>,
     pub quality: Option<i32>,
+    pub format: Option<String>,
+    pub background: Option<String>,
+    pub fit: Option<String>,
+    /// Study view, e.g. `grayscale` or `posterize-3,mirror`.
+    pub view: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -41,124 +220,425 @@ pub struct BatchResult {
     pub error: Option<String>,
 }
 
-fn cache_key(path: &str, width: i32, height: i32, quality: i32) -> u64 {
-    let mut h = DefaultHasher::new();
-    path.hash(&mut h);
-    width.hash(&mut h);
-    height.hash(&mut h);
-    quality.hash(&mut h);
-    h.finish()
+/// Builds a spec from request parameters. Explicit parameters win over the
+/// named profile, which wins over the `imageWidth`/`imageHeight`/
+/// `imageQuality` settings. Without an explicit background the
+/// `thumb_background` setting applies. Profiles don't pin a study view.
+pub(crate) fn thumb_spec(
+    profile: Option<&str>,
+    width: Option<i32>,
+    height: Option<i32>,
+    quality: Option<i32>,
+    format: Option<&str>,
+    background: Option<&str>,
+    fit: Option<&str>,
+    view: Option<&str>,
+) -> Result<ThumbSpec, String> {
+    let config = get_config();
+    let base = match profile {
+        Some(name) => config
+            .thumb_profiles
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("Unknown thumbnail profile: {}", name))?,
+        None => ThumbProfile {
+            width: config.imageWidth,
+            height: config.imageHeight,
+            quality: config.imageQuality,
+            format: String::new(),
+            fit: String::new(),
+        },
+    };
+
+    let format = format.unwrap_or(&base.format);
+    let fit = fit.unwrap_or(&base.fit);
+    let background = background.unwrap_or(&config.thumb_background);
+
+    Ok(ThumbSpec {
+        width: width.map_or(base.width, |w| w.max(1) as u32),
+        height: height.map_or(base.height, |h| h.max(1) as u32),
+        quality: quality.map_or(base.quality, |q| q.clamp(1, 100) as u8),
+        format: ThumbFormat::parse(format).ok_or_else(|| format!("Unknown thumbnail format: {}", format))?,
+        background: Background::parse(background)
+            .ok_or_else(|| format!("Invalid thumbnail background: {}", background))?,
+        fit: FitMode::parse(fit).ok_or_else(|| format!("Unknown fit mode: {}", fit))?,
+        view: match view {
+            Some(view) => StudyView::parse(view).ok_or_else(|| format!("Unknown study view: {}", view))?,
+            None => StudyView::default(),
+        },
+    })
+}
+
+fn get_cached(key: &ThumbKey) -> Option<Vec<u8>> {
+    let data = IMAGE_CACHE.lock().unwrap().get(key)?;
+    Some(data.as_ref().clone())
 }
 
-fn now_secs() -> u64 {
-    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+fn set_cached(key: ThumbKey, path: &str, data: Vec<u8>) {
+    IMAGE_CACHE.lock().unwrap().insert(key, path, Arc::new(data));
 }
 
-fn get_cached(key: u64) -> Option<Vec<u8>> {
-    if let Some(mut entry) = IMAGE_CACHE.get_mut(&key) {
-        let age = now_secs() - entry.timestamp;
-        let ttl = if entry.access_count > 10 { 14400 } else if entry.access_count > 3 { 7200 } else { 3600 };
-        if age < ttl {
-            entry.access_count += 1;
-            return Some(entry.data.clone());
-        }
-        drop(entry);
-        IMAGE_CACHE.remove(&key);
+pub fn evict_path_prefix(prefix: &str) {
+    IMAGE_CACHE.lock().unwrap().retain(|_, v| !v.path.starts_with(prefix));
+    thumbcache::forget_sources(prefix);
+}
+
+/// Drops every cached rendition made with `profile`'s settings, in memory
+/// and on disk. Called with the old value when a profile is edited or removed.
+pub fn invalidate_profile(profile: &ThumbProfile) {
+    let (Some(format), Some(fit)) = (ThumbFormat::parse(&profile.format), FitMode::parse(&profile.fit)) else {
+        return;
+    };
+    let spec = ThumbSpec {
+        width: profile.width,
+        height: profile.height,
+        quality: profile.quality,
+        format,
+        background: Background::None,
+        fit,
+        view: StudyView::default(),
+    };
+
+    IMAGE_CACHE.lock().unwrap().retain(|key, _| !spec.matches_any_variant(key));
+    let removed = thumbcache::purge(|key| spec.matches_any_variant(key));
+    log_info!("Invalidated {} cached thumbnails for a changed profile", removed);
+}
+
+/// Applies a new memory budget, evicting immediately if it shrank.
+pub fn set_memory_budget_mb(mb: u64) {
+    let mut cache = IMAGE_CACHE.lock().unwrap();
+    cache.stats.budget_bytes = mb * MB;
+    cache.evict();
+}
+
+#[tauri::command]
+pub fn get_thumbnail_cache_stats() -> ThumbnailCacheStats {
+    ThumbnailCacheStats {
+        memory: IMAGE_CACHE.lock().unwrap().snapshot(),
+        disk: thumbcache::stats(),
+        render: *RENDER_STATS.lock().unwrap(),
     }
-    None
 }
 
-fn set_cached(key: u64, path: &str, data: Vec<u8>) {
-    IMAGE_CACHE.insert(key, CachedImage { data, timestamp: now_secs(), access_count: 1, path: path.to_string() });
+// Memory first, then disk, then a fresh decode that fills both.
+pub(crate) fn load_thumbnail(
+    path: &str,
+    spec: &ThumbSpec,
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    if !Path::new(path).exists() {
+        return Err(format!("File does not exist: {}", path).into());
+    }
+
+    let key = thumbcache::key_for(Path::new(path), spec)?;
+    load_keyed(path, key, spec)
 }
 
-pub fn evict_path_prefix(prefix: &str) {
-    IMAGE_CACHE.retain(|_, v| !v.path.starts_with(prefix));
+/// Like `load_thumbnail` for callers that already resolved the key.
+pub(crate) fn load_keyed(
+    path: &str,
+    key: ThumbKey,
+    spec: &ThumbSpec,
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(cached) = get_cached(&key) {
+        return Ok(cached);
+    }
+    if let Some(stored) = thumbcache::load(&key) {
+        set_cached(key, path, stored.clone());
+        return Ok(stored);
+    }
+
+    let bytes = process_image(path, spec)?;
+    thumbcache::store(&key, &bytes);
+    set_cached(key, path, bytes.clone());
+    Ok(bytes)
 }
 
 #[tauri::command]
 pub async fn handle_optimize_image_request(
     src: String,
+    profile: Option<String>,
     width: Option<i32>,
     height: Option<i32>,
     quality: Option<i32>,
+    format: Option<String>,
+    background: Option<String>,
+    fit: Option<String>,
+    view: Option<String>,
 ) -> Result<String, String> {
-    let width = width.unwrap_or(1280);
-    let height = height.unwrap_or(720);
-    let quality = quality.unwrap_or(90);
-    let key = cache_key(&src, width, height, quality);
+    let spec = thumb_spec(
+        profile.as_deref(),
+        width,
+        height,
+        quality,
+        format.as_deref(),
+        background.as_deref(),
+        fit.as_deref(),
+        view.as_deref(),
+    )?;
 
-    if let Some(cached) = get_cached(key) {
-        return Ok(general_purpose::STANDARD.encode(&cached));
-    }
-
-    let path_for_cache = src.clone();
-    let bytes = task::spawn_blocking(move || process_image(&src, width, height, quality))
+    let bytes = task::spawn_blocking(move || load_thumbnail(&src, &spec))
         .await
         .map_err(|e| e.to_string())?
         .map_err(|e| e.to_string())?;
 
-    set_cached(key, &path_for_cache, bytes.clone());
     Ok(general_purpose::STANDARD.encode(&bytes))
 }
 
 #[tauri::command]
 pub async fn batch_optimize_images(request: BatchRequest) -> Result<Vec<BatchResult>, String> {
-    let width = request.width.unwrap_or(1280);
-    let height = request.height.unwrap_or(720);
-    let quality = request.quality.unwrap_or(90);
-
-    Ok(request.paths
-        .into_par_iter()
-        .map(|path| {
-            let key = cache_key(&path, width, height, quality);
-            if let Some(cached) = get_cached(key) {
-                return BatchResult { path, data: Some(general_purpose::STANDARD.encode(&cached)), error: None };
-            }
-            match process_image(&path, width, height, quality) {
-                Ok(bytes) => {
-                    set_cached(key, &path, bytes.clone());
-                    BatchResult { path, data: Some(general_purpose::STANDARD.encode(&bytes)), error: None }
-                }
+    let spec = thumb_spec(
+        request.profile.as_deref(),
+        request.width,
+        request.height,
+        request.quality,
+        request.format.as_deref(),
+        request.background.as_deref(),
+        request.fit.as_deref(),
+        request.view.as_deref(),
+    )?;
+
+    task::spawn_blocking(move || {
+        let started = Instant::now();
+        let results: Vec<BatchResult> = request.paths
+            .into_par_iter()
+            .map(|path| match load_thumbnail(&path, &spec) {
+                Ok(bytes) => BatchResult { path, data: Some(general_purpose::STANDARD.encode(&bytes)), error: None },
                 Err(e) => BatchResult { path, data: None, error: Some(e.to_string()) },
-            }
-        })
-        .collect())
+            })
+            .collect();
+        log_info!("Thumbnail batch of {} took {} ms", results.len(), started.elapsed().as_millis());
+        results
+    })
+    .await
+    .map_err(|e| e.to_string())
 }
 
 fn process_image(
     path: &str,
-    max_w: i32,
-    max_h: i32,
-    quality: i32,
+    spec: &ThumbSpec,
 ) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
-    if !Path::new(path).exists() {
-        return Err(format!("File does not exist: {}", path).into());
+    let media = mediatype::detect(Path::new(path));
+    if spec.format.animates() && media.kind == MediaKind::AnimatedImage {
+        return process_animation(path, spec);
     }
 
-    let img = image::open(path)?.into_rgb8();
-    let (src_w, src_h) = img.dimensions();
-    let (dst_w, dst_h) = fit_dimensions(src_w, src_h, max_w as u32, max_h as u32);
+    let started = Instant::now();
+    let decoded = match media.format {
+        // No decoder in this build: the grid still gets something to show
+        Some(format) if media.kind == MediaKind::Unsupported => DecodedImage {
+            image: codecs::fallback_icon(format, spec.width.max(spec.height)),
+            icc_profile: None,
+            source: DecodeSource::FallbackIcon,
+        },
+        _ => mediatype::decode_image_for(Path::new(path), decode_target(spec))?,
+    };
+    let decoded_at = Instant::now();
+
+    let img = decoded.image;
+    let (crop, dst_w, dst_h) = plan_fit(&img, spec);
+
+    // Colour conversion runs on the thumbnail, not the full-size source
+    let to_srgb = |img: &mut DynamicImage| {
+        if let Some(icc) = &decoded.icc_profile {
+            mediatype::to_srgb(img, icc);
+        }
+    };
+
+    let (output, transparent) = if !img.color().has_alpha() {
+        let mut resized = DynamicImage::ImageRgb8(resize_simd(img.into_rgb8(), crop, dst_w, dst_h)?);
+        to_srgb(&mut resized);
+        apply_view(&mut resized, spec.view);
+        (resized, false)
+    } else {
+        // Resize with alpha first; whether anything is actually see-through is
+        // cheaper to answer on the thumbnail than on the source.
+        let mut resized = DynamicImage::ImageRgba8(resize_simd(img.into_rgba8(), crop, dst_w, dst_h)?);
+        to_srgb(&mut resized);
+        apply_view(&mut resized, spec.view);
+        let resized = resized.into_rgba8();
+        let transparent = resized.pixels().any(|p| p[3] < u8::MAX);
+        let keep_alpha = transparent
+            && spec.background == Background::None
+            && spec.format != ThumbFormat::Jpeg;
+
+        if keep_alpha {
+            (DynamicImage::ImageRgba8(resized), true)
+        } else if transparent {
+            (DynamicImage::ImageRgb8(flatten(&resized, spec.background)), false)
+        } else {
+            (DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(resized).into_rgb8()), false)
+        }
+    };
+    let resized_at = Instant::now();
+
+    let bytes = encode(&output, spec.format, transparent, spec.quality)?;
+    RENDER_STATS.lock().unwrap().record(
+        decoded.source,
+        decoded_at - started,
+        resized_at - decoded_at,
+        resized_at.elapsed(),
+    );
+    Ok(bytes)
+}
+
+// Every frame gets the crop planned on the first one, so the subject stays
+// put, and the result is encoded as lossy animated WebP.
+fn process_animation(
+    path: &str,
+    spec: &ThumbSpec,
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    let started = Instant::now();
+    let mut frames = animation::open_frames(Path::new(path))?;
+    let first = frames.next().ok_or("Animation has no frames")??;
+    let (crop, dst_w, dst_h) = plan_fit(&DynamicImage::ImageRgba8(first.buffer().clone()), spec);
 
-    let resized = if src_w == dst_w && src_h == dst_h {
-        img
+    let mut resized = Vec::new();
+    let mut delays = Vec::new();
+    let mut resize_time = Duration::ZERO;
+    for frame in std::iter::once(Ok(first)).chain(frames).take(MAX_ANIMATION_FRAMES) {
+        let frame = frame?;
+        delays.push(animation::delay_ms(&frame));
+
+        let resize_started = Instant::now();
+        let mut small = resize_simd(frame.into_buffer(), crop, dst_w, dst_h)?;
+        apply_view_to(&mut small, spec.view);
+        let small = match spec.background {
+            Background::None => small,
+            background => DynamicImage::ImageRgb8(flatten(&small, background)).into_rgba8(),
+        };
+        resized.push(small);
+        resize_time += resize_started.elapsed();
+    }
+    let decoded_at = Instant::now();
+
+    let bytes = if resized.len() == 1 {
+        // A sniffed animation can still decode to one frame; like a still,
+        // it goes out as RGB when opaque, which is all JPEG accepts
+        let frame = resized.remove(0);
+        let transparent = frame.pixels().any(|p| p[3] < u8::MAX);
+        let frame = if transparent {
+            DynamicImage::ImageRgba8(frame)
+        } else {
+            DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(frame).into_rgb8())
+        };
+        encode(&frame, spec.format, transparent, spec.quality)?
     } else {
-        resize_simd(img, dst_w, dst_h)?
+        encode_animation(&resized, &delays, spec.quality, spec.format == ThumbFormat::WebPLossless)?
     };
+    RENDER_STATS.lock().unwrap().record(
+        DecodeSource::Full,
+        (decoded_at - started).saturating_sub(resize_time),
+        resize_time,
+        decoded_at.elapsed(),
+    );
+    Ok(bytes)
+}
 
-    encode_jpeg(&DynamicImage::ImageRgb8(resized), quality as u8)
+fn encode_animation(
+    frames: &[RgbaImage],
+    delays: &[u32],
+    quality: u8,
+    lossless: bool,
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    let (w, h) = frames[0].dimensions();
+    let mut config = webp::WebPConfig::new().map_err(|_| "Failed to initialise the WebP encoder")?;
+    config.quality = quality as f32;
+    config.lossless = lossless as i32;
+
+    let mut encoder = webp::AnimEncoder::new(w, h, &config);
+    encoder.set_loop_count(0);
+    let mut timestamp = 0i32;
+    for (frame, delay) in frames.iter().zip(delays) {
+        encoder.add_frame(webp::AnimFrame::from_rgba(frame.as_raw(), w, h, timestamp));
+        timestamp += *delay as i32;
+    }
+
+    let data = encoder
+        .try_encode()
+        .map_err(|e| format!("Failed to encode animated preview: {:?}", e))?;
+    let mut data = data.to_vec();
+    set_total_duration(&mut data, timestamp as u32);
+    Ok(data)
 }
 
-fn resize_simd(
-    img: RgbImage,
+// libwebp takes each frame's duration from the next frame's timestamp, and
+// the encoder closes the stream without an end time, so the last frame gets
+// the average duration instead of its own. Rewrites the last ANMF chunk so
+// the animation runs for `total_ms`.
+fn set_total_duration(webp: &mut [u8], total_ms: u32) {
+    let mut offset = 12; // "RIFF" <size> "WEBP"
+    let mut last_frame = None;
+    let mut elapsed = 0u32;
+    while offset + 8 <= webp.len() {
+        let size = u32::from_le_bytes(webp[offset + 4..offset + 8].try_into().unwrap()) as usize;
+        if &webp[offset..offset + 4] == b"ANMF" && offset + 24 <= webp.len() {
+            if let Some(previous) = last_frame {
+                elapsed += frame_duration(webp, previous);
+            }
+            last_frame = Some(offset + 8);
+        }
+        offset += 8 + size + (size & 1);
+    }
+
+    // Bytes 12..15 of an ANMF payload hold the duration (24-bit LE)
+    if let Some(payload) = last_frame {
+        let duration = total_ms.saturating_sub(elapsed).clamp(1, 0xFF_FFFF);
+        webp[payload + 12..payload + 15].copy_from_slice(&duration.to_le_bytes()[..3]);
+    }
+}
+
+fn frame_duration(webp: &[u8], payload: usize) -> u32 {
+    let b = &webp[payload + 12..payload + 15];
+    u32::from_le_bytes([b[0], b[1], b[2], 0])
+}
+
+// The smallest decode `plan_fit` can work from without losing output pixels.
+fn decode_target(spec: &ThumbSpec) -> DecodeTarget {
+    match spec.fit {
+        FitMode::Contain => DecodeTarget { width: spec.width, height: spec.height, cover: false },
+        FitMode::Cover | FitMode::Smart => DecodeTarget { width: spec.width, height: spec.height, cover: true },
+        FitMode::Square => {
+            let side = spec.width.min(spec.height);
+            DecodeTarget { width: side, height: side, cover: true }
+        }
+    }
+}
+
+/// Scales all of `img` to exactly `width`×`height`, keeping alpha if it has any.
+pub(crate) fn resize_exact(
+    img: DynamicImage,
+    width: u32,
+    height: u32,
+) -> Result<DynamicImage, Box<dyn std::error::Error + Send + Sync>> {
+    let crop = Crop::full(img.width(), img.height());
+    Ok(if img.color().has_alpha() {
+        DynamicImage::ImageRgba8(resize_simd(img.into_rgba8(), crop, width, height)?)
+    } else {
+        DynamicImage::ImageRgb8(resize_simd(img.into_rgb8(), crop, width, height)?)
+    })
+}
+
+fn resize_simd<P: Pixel<Subpixel = u8>>(
+    img: ImageBuffer<P, Vec<u8>>,
+    crop: Crop,
     dst_w: u32,
     dst_h: u32,
-) -> Result<RgbImage, Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<ImageBuffer<P, Vec<u8>>, Box<dyn std::error::Error + Send + Sync>> {
     let (src_w, src_h) = img.dimensions();
-    let src = fr::images::Image::from_vec_u8(src_w, src_h, img.into_raw(), fr::PixelType::U8x3)?;
-    let mut dst = fr::images::Image::new(dst_w, dst_h, fr::PixelType::U8x3);
+    if crop == Crop::full(src_w, src_h) && src_w == dst_w && src_h == dst_h {
+        return Ok(img);
+    }
 
-    let scale = (dst_w * dst_h) as f64 / (src_w * src_h) as f64;
+    // RGBA is resized with premultiplied alpha so edges don't pick up dark fringes
+    let pixel_type = match P::CHANNEL_COUNT {
+        3 => fr::PixelType::U8x3,
+        4 => fr::PixelType::U8x4,
+        n => return Err(format!("Unsupported channel count: {}", n).into()),
+    };
+    let src = fr::images::Image::from_vec_u8(src_w, src_h, img.into_raw(), pixel_type)?;
+    let mut dst = fr::images::Image::new(dst_w, dst_h, pixel_type);
+
+    let scale = (dst_w * dst_h) as f64 / (crop.w * crop.h) as f64;
     let alg = if scale <= 0.25 {
         fr::ResizeAlg::Convolution(fr::FilterType::Box)
     } else if scale <= 1.0 {
@@ -166,22 +646,238 @@ fn resize_simd(
     } else {
         fr::ResizeAlg::Convolution(fr::FilterType::Lanczos3)
     };
+    let options = fr::ResizeOptions::new()
+        .resize_alg(alg)
+        .crop(crop.x as f64, crop.y as f64, crop.w as f64, crop.h as f64);
 
-    fr::Resizer::new().resize(&src, &mut dst, &fr::ResizeOptions::new().resize_alg(alg))?;
+    fr::Resizer::new().resize(&src, &mut dst, &options)?;
 
-    RgbImage::from_raw(dst_w, dst_h, dst.into_vec())
+    ImageBuffer::from_raw(dst_w, dst_h, dst.into_vec())
         .ok_or_else(|| "Failed to construct resized image".into())
 }
 
-fn encode_jpeg(
+// ── fit modes ─────────────────────────────────────────────────────────────────
+
+/// Region of the source that ends up in the thumbnail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Crop {
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+}
+
+impl Crop {
+    fn full(w: u32, h: u32) -> Crop {
+        Crop { x: 0, y: 0, w, h }
+    }
+}
+
+// Returns the source region and the output size for the spec's fit mode.
+fn plan_fit(img: &DynamicImage, spec: &ThumbSpec) -> (Crop, u32, u32) {
+    let (src_w, src_h) = img.dimensions();
+    match spec.fit {
+        FitMode::Contain => {
+            let (w, h) = fit_dimensions(src_w, src_h, spec.width, spec.height);
+            (Crop::full(src_w, src_h), w, h)
+        }
+        FitMode::Cover => plan_cover(img, spec.width, spec.height, false),
+        FitMode::Square => {
+            let side = spec.width.min(spec.height);
+            plan_cover(img, side, side, false)
+        }
+        FitMode::Smart => plan_cover(img, spec.width, spec.height, true),
+    }
+}
+
+// Takes the largest window with the box's aspect ratio and scales it to the
+// box. Sources smaller than the box are cropped but never upscaled.
+fn plan_cover(img: &DynamicImage, box_w: u32, box_h: u32, smart: bool) -> (Crop, u32, u32) {
+    let (src_w, src_h) = img.dimensions();
+    let (crop_w, crop_h) = if src_w as u64 * box_h as u64 > src_h as u64 * box_w as u64 {
+        (((src_h as u64 * box_w as u64) / box_h as u64).max(1) as u32, src_h)
+    } else {
+        (src_w, ((src_w as u64 * box_h as u64) / box_w as u64).max(1) as u32)
+    };
+
+    let (x, y) = if smart {
+        smart_offset(img, crop_w, crop_h)
+    } else {
+        ((src_w - crop_w) / 2, (src_h - crop_h) / 2)
+    };
+
+    let (dst_w, dst_h) = if crop_w < box_w { (crop_w, crop_h) } else { (box_w, box_h) };
+    (Crop { x, y, w: crop_w, h: crop_h }, dst_w, dst_h)
+}
+
+// Slides the crop window along its free axis over a small grayscale copy
+// and keeps the position with the most edge energy, so subjects win over
+// flat sky, walls or studio backdrops. Ties go to the most central position.
+fn smart_offset(img: &DynamicImage, crop_w: u32, crop_h: u32) -> (u32, u32) {
+    let (src_w, src_h) = img.dimensions();
+    let horizontal = crop_w < src_w;
+    if !horizontal && crop_h >= src_h {
+        return (0, 0);
+    }
+
+    let small = img.thumbnail(SMART_SAMPLE, SMART_SAMPLE).to_luma8();
+    let (sw, sh) = small.dimensions();
+    let (len, src_len, window_src) = if horizontal { (sw, src_w, crop_w) } else { (sh, src_h, crop_h) };
+
+    // Edge energy per column (horizontal slide) or row (vertical slide)
+    let mut energy = vec![0u64; len as usize];
+    for y in 1..sh {
+        for x in 1..sw {
+            let p = small.get_pixel(x, y)[0] as i32;
+            let dx = (p - small.get_pixel(x - 1, y)[0] as i32).unsigned_abs();
+            let dy = (p - small.get_pixel(x, y - 1)[0] as i32).unsigned_abs();
+            energy[if horizontal { x } else { y } as usize] += (dx + dy) as u64;
+        }
+    }
+
+    let scale = len as f64 / src_len as f64;
+    let window = ((window_src as f64 * scale).round() as usize).clamp(1, len as usize);
+    let centre = (len as usize - window) as f64 / 2.0;
+
+    let mut sum: u64 = energy[..window].iter().sum();
+    let mut best = (sum, 0usize);
+    for start in 1..=(len as usize - window) {
+        sum = sum + energy[start + window - 1] - energy[start - 1];
+        let closer = (start as f64 - centre).abs() < (best.1 as f64 - centre).abs();
+        if sum > best.0 || (sum == best.0 && closer) {
+            best = (sum, start);
+        }
+    }
+
+    let offset = ((best.1 as f64 / scale).round() as u32).min(src_len - window_src);
+    if horizontal {
+        (offset, 0)
+    } else {
+        (0, offset)
+    }
+}
+
+// Composites onto the requested background; JPEG without one gets white.
+fn flatten(img: &RgbaImage, background: Background) -> RgbImage {
+    RgbImage::from_fn(img.width(), img.height(), |x, y| {
+        let p = img.get_pixel(x, y);
+        let bg = match background {
+            Background::Color(color) => color,
+            Background::Checkerboard if (x / CHECKER_CELL + y / CHECKER_CELL) % 2 == 0 => CHECKER_LIGHT,
+            Background::Checkerboard => CHECKER_DARK,
+            Background::None => [255, 255, 255],
+        };
+        let a = p[3] as u32;
+        Rgb(std::array::from_fn(|i| {
+            ((p[i] as u32 * a + bg[i] as u32 * (255 - a) + 127) / 255) as u8
+        }))
+    })
+}
+
+// ── study views ───────────────────────────────────────────────────────────────
+
+fn apply_view(img: &mut DynamicImage, view: StudyView) {
+    match img {
+        DynamicImage::ImageRgb8(buf) => apply_view_to(buf, view),
+        DynamicImage::ImageRgba8(buf) => apply_view_to(buf, view),
+        _ => {}
+    }
+}
+
+// Tones are built on relative luminance in linear light, so a grey keeps
+// the value the eye reads from the colour; alpha is left alone.
+fn apply_view_to<P: Pixel<Subpixel = u8>>(img: &mut ImageBuffer<P, Vec<u8>>, view: StudyView) {
+    if view.mirror {
+        imageops::flip_horizontal_in_place(img);
+    }
+
+    let levels: [u8; 256] = match view.tone {
+        Tone::Color => return,
+        Tone::Edges => return sobel_edges(img),
+        Tone::Grayscale => std::array::from_fn(|v| v as u8),
+        Tone::Posterize(steps) => {
+            let steps = steps as usize;
+            std::array::from_fn(|v| (v * steps / 256 * 255 / (steps - 1)) as u8)
+        }
+        Tone::Notan(threshold) => std::array::from_fn(|v| if v >= threshold as usize { 255 } else { 0 }),
+    };
+    for p in img.pixels_mut() {
+        let value = levels[luminance(p.channels()) as usize];
+        p.channels_mut()[..3].fill(value);
+    }
+}
+
+// Sobel gradient magnitude of the luminance, inverted so outlines read as
+// pencil lines on white paper.
+fn sobel_edges<P: Pixel<Subpixel = u8>>(img: &mut ImageBuffer<P, Vec<u8>>) {
+    let (w, h) = img.dimensions();
+    let luma: Vec<i32> = img.pixels().map(|p| luminance(p.channels()) as i32).collect();
+    let at = |x: u32, y: u32, dx: i32, dy: i32| {
+        let x = (x as i32 + dx).clamp(0, w as i32 - 1) as usize;
+        let y = (y as i32 + dy).clamp(0, h as i32 - 1) as usize;
+        luma[y * w as usize + x]
+    };
+
+    for (x, y, p) in img.enumerate_pixels_mut() {
+        let gx = at(x, y, 1, -1) + 2 * at(x, y, 1, 0) + at(x, y, 1, 1)
+            - at(x, y, -1, -1) - 2 * at(x, y, -1, 0) - at(x, y, -1, 1);
+        let gy = at(x, y, -1, 1) + 2 * at(x, y, 0, 1) + at(x, y, 1, 1)
+            - at(x, y, -1, -1) - 2 * at(x, y, 0, -1) - at(x, y, 1, -1);
+        let magnitude = ((gx * gx + gy * gy) as f32).sqrt() / 2.0;
+        p.channels_mut()[..3].fill(255 - magnitude.min(255.0) as u8);
+    }
+}
+
+// sRGB-encoded value of a pixel's relative luminance.
+fn luminance(rgb: &[u8]) -> u8 {
+    let y = 0.2126 * SRGB_TO_LINEAR[rgb[0] as usize]
+        + 0.7152 * SRGB_TO_LINEAR[rgb[1] as usize]
+        + 0.0722 * SRGB_TO_LINEAR[rgb[2] as usize];
+    let encoded = if y <= 0.003_130_8 { 12.92 * y } else { 1.055 * y.powf(1.0 / 2.4) - 0.055 };
+    (encoded * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+// Still WebP goes through libwebp, lossy at `quality` like JPEG; the
+// `image` crate only encodes lossless. `WebPLossless` has to be asked for.
+pub(crate) fn encode(
     img: &DynamicImage,
+    format: ThumbFormat,
+    transparent: bool,
     quality: u8,
 ) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    let format = match format {
+        ThumbFormat::Auto if transparent => ThumbFormat::WebP,
+        ThumbFormat::Auto => ThumbFormat::Jpeg,
+        explicit => explicit,
+    };
+
     let mut buf = Cursor::new(Vec::new());
-    JpegEncoder::new_with_quality(&mut buf, quality).encode_image(img)?;
+    match format {
+        ThumbFormat::WebP => return encode_webp(img, quality),
+        ThumbFormat::WebPLossless => img.write_with_encoder(WebPEncoder::new_lossless(&mut buf))?,
+        ThumbFormat::Png => img.write_with_encoder(PngEncoder::new(&mut buf))?,
+        _ => img.write_with_encoder(JpegEncoder::new_with_quality(&mut buf, quality))?,
+    }
     Ok(buf.into_inner())
 }
 
+fn encode_webp(img: &DynamicImage, quality: u8) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut config = webp::WebPConfig::new().map_err(|_| "Failed to initialise the WebP encoder")?;
+    config.quality = quality as f32;
+    config.lossless = 0;
+
+    let data = match img {
+        DynamicImage::ImageRgb8(rgb) => webp::Encoder::from_rgb(rgb.as_raw(), rgb.width(), rgb.height())
+            .encode_advanced(&config),
+        other => {
+            let rgba = other.to_rgba8();
+            webp::Encoder::from_rgba(rgba.as_raw(), rgba.width(), rgba.height()).encode_advanced(&config)
+        }
+    }
+    .map_err(|e| format!("Failed to encode WebP: {:?}", e))?;
+    Ok(data.to_vec())
+}
+
 // Keep even dimensions for better JPEG compression ratios
 fn fit_dimensions(src_w: u32, src_h: u32, max_w: u32, max_h: u32) -> (u32, u32) {
     if src_w <= max_w && src_h <= max_h {
@@ -196,19 +892,3 @@ fn fit_dimensions(src_w: u32, src_h: u32, max_w: u32, max_h: u32) -> (u32, u32)
     };
     (new_w & !1, new_h & !1)
 }
-
-pub fn start_cache_cleanup() {
-    tauri::async_runtime::spawn(async {
-        let mut interval = tokio::time::interval(Duration::from_secs(1800));
-        loop {
-            interval.tick().await;
-            let now = now_secs();
-            IMAGE_CACHE.retain(|_, v| {
-                let age = now - v.timestamp;
-                if v.access_count > 10 { age < 14400 }
-                else if v.access_count > 3 { age < 7200 }
-                else { age < 3600 }
-            });
-        }
-    });
-}
@@ -0,0 +1,634 @@
+use crate::modules::codecs;
+use crate::modules::raw;
+use image::metadata::Orientation;
+use image::{DynamicImage, GrayImage, ImageDecoder, RgbImage, RgbaImage};
+use moxcms::{ColorProfile, DataColorSpace, Layout, TransformOptions};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+
+// Enough to cover every signature below, including a leading XML prolog on SVGs.
+const SNIFF_LEN: usize = 512;
+
+// Browsers and download managers write to these until the transfer finishes.
+const PARTIAL_EXTENSIONS: &[&str] = &["crdownload", "part", "partial", "download", "tmp"];
+
+// Allocation cap for `decode_large_image`: a gigapixel of RGBA.
+const LARGE_IMAGE_ALLOC: u64 = 4 * 1024 * 1024 * 1024;
+
+// ── types ─────────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MediaKind {
+    /// A still image we can decode.
+    Image,
+    /// A decodable image with more than one frame.
+    AnimatedImage,
+    /// A recognised image format without a decoder in this build.
+    Unsupported,
+    /// Anything else: text, archives, partial downloads.
+    Other,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MediaFormat {
+    Jpeg,
+    Png,
+    Gif,
+    Bmp,
+    Tiff,
+    WebP,
+    Avif,
+    Heic,
+    Jxl,
+    Qoi,
+    Psd,
+    Svg,
+    /// TIFF-based camera RAW (CR2, NEF, ARW, DNG); see `raw`.
+    Raw,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct MediaType {
+    pub kind: MediaKind,
+    pub format: Option<MediaFormat>,
+}
+
+impl MediaKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MediaKind::Image => "image",
+            MediaKind::AnimatedImage => "animated_image",
+            MediaKind::Unsupported => "unsupported",
+            MediaKind::Other => "other",
+        }
+    }
+
+    pub fn parse(s: &str) -> MediaKind {
+        match s {
+            "image" => MediaKind::Image,
+            "animated_image" => MediaKind::AnimatedImage,
+            "unsupported" => MediaKind::Unsupported,
+            _ => MediaKind::Other,
+        }
+    }
+}
+
+impl MediaFormat {
+    /// The `image` crate format used to decode this file, if this build has one.
+    pub fn image_format(&self) -> Option<image::ImageFormat> {
+        match self {
+            MediaFormat::Jpeg => Some(image::ImageFormat::Jpeg),
+            MediaFormat::Png => Some(image::ImageFormat::Png),
+            MediaFormat::Gif => Some(image::ImageFormat::Gif),
+            MediaFormat::Bmp => Some(image::ImageFormat::Bmp),
+            MediaFormat::Tiff => Some(image::ImageFormat::Tiff),
+            MediaFormat::WebP => Some(image::ImageFormat::WebP),
+            #[cfg(feature = "avif")]
+            MediaFormat::Avif => Some(image::ImageFormat::Avif),
+            #[cfg(feature = "qoi")]
+            MediaFormat::Qoi => Some(image::ImageFormat::Qoi),
+            _ => None,
+        }
+    }
+
+    /// Whether this build can decode the format, through `image` or one of
+    /// the optional decoders in `codecs`.
+    pub fn is_decodable(&self) -> bool {
+        self.image_format().is_some() || codecs::supports(*self)
+    }
+
+    /// Short uppercase name, as shown on fallback icons.
+    pub fn label(&self) -> &'static str {
+        match self {
+            MediaFormat::Jpeg => "JPG",
+            MediaFormat::Png => "PNG",
+            MediaFormat::Gif => "GIF",
+            MediaFormat::Bmp => "BMP",
+            MediaFormat::Tiff => "TIFF",
+            MediaFormat::WebP => "WEBP",
+            MediaFormat::Avif => "AVIF",
+            MediaFormat::Heic => "HEIC",
+            MediaFormat::Jxl => "JXL",
+            MediaFormat::Qoi => "QOI",
+            MediaFormat::Psd => "PSD",
+            MediaFormat::Svg => "SVG",
+            MediaFormat::Raw => "RAW",
+        }
+    }
+
+    pub fn mime(&self) -> &'static str {
+        match self {
+            MediaFormat::Jpeg => "image/jpeg",
+            MediaFormat::Png => "image/png",
+            MediaFormat::Gif => "image/gif",
+            MediaFormat::Bmp => "image/bmp",
+            MediaFormat::Tiff => "image/tiff",
+            MediaFormat::WebP => "image/webp",
+            MediaFormat::Avif => "image/avif",
+            MediaFormat::Heic => "image/heic",
+            MediaFormat::Jxl => "image/jxl",
+            MediaFormat::Qoi => "image/qoi",
+            MediaFormat::Psd => "image/vnd.adobe.photoshop",
+            MediaFormat::Svg => "image/svg+xml",
+            MediaFormat::Raw => "image/x-raw",
+        }
+    }
+}
+
+impl MediaType {
+    pub const OTHER: MediaType = MediaType { kind: MediaKind::Other, format: None };
+
+    pub fn is_decodable(&self) -> bool {
+        matches!(self.kind, MediaKind::Image | MediaKind::AnimatedImage)
+    }
+
+    /// True for anything that should show up in the library grid.
+    pub fn is_media(&self) -> bool {
+        self.kind != MediaKind::Other
+    }
+}
+
+// ── detection ─────────────────────────────────────────────────────────────────
+
+/// Identifies a format from the first bytes of a file.
+pub fn sniff(head: &[u8]) -> Option<MediaFormat> {
+    if head.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        return Some(MediaFormat::Jpeg);
+    }
+    if head.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Some(MediaFormat::Png);
+    }
+    if head.starts_with(b"GIF87a") || head.starts_with(b"GIF89a") {
+        return Some(MediaFormat::Gif);
+    }
+    if head.starts_with(b"BM") && head.len() >= 14 {
+        return Some(MediaFormat::Bmp);
+    }
+    if head.starts_with(b"II*\0") || head.starts_with(b"MM\0*") {
+        return Some(MediaFormat::Tiff);
+    }
+    if head.len() >= 12 && &head[..4] == b"RIFF" && &head[8..12] == b"WEBP" {
+        return Some(MediaFormat::WebP);
+    }
+    if head.len() >= 12 && &head[4..8] == b"ftyp" {
+        return sniff_isobmff(head);
+    }
+    if head.starts_with(&[0xFF, 0x0A])
+        || head.starts_with(b"\0\0\0\x0CJXL \r\n\x87\n")
+    {
+        return Some(MediaFormat::Jxl);
+    }
+    if head.starts_with(b"qoif") {
+        return Some(MediaFormat::Qoi);
+    }
+    if head.starts_with(b"8BPS") {
+        return Some(MediaFormat::Psd);
+    }
+    if looks_like_svg(head) {
+        return Some(MediaFormat::Svg);
+    }
+    None
+}
+
+// HEIF and AVIF share the ISO base media container; the brands tell them apart.
+fn sniff_isobmff(head: &[u8]) -> Option<MediaFormat> {
+    let box_len = u32::from_be_bytes(head[..4].try_into().ok()?) as usize;
+    let end = box_len.min(head.len());
+    let brands: Vec<&[u8]> = std::iter::once(&head[8..12])
+        .chain(head.get(16..end).unwrap_or(&[]).chunks_exact(4))
+        .collect();
+
+    if brands.iter().any(|b| *b == b"avif" || *b == b"avis") {
+        Some(MediaFormat::Avif)
+    } else if brands
+        .iter()
+        .any(|b| matches!(*b, b"heic" | b"heix" | b"hevc" | b"hevx" | b"heim" | b"heis" | b"mif1" | b"msf1"))
+    {
+        Some(MediaFormat::Heic)
+    } else {
+        None
+    }
+}
+
+fn looks_like_svg(head: &[u8]) -> bool {
+    let text = String::from_utf8_lossy(head);
+    let text = text.trim_start_matches('\u{feff}').trim_start();
+    (text.starts_with("<?xml") || text.starts_with("<svg") || text.starts_with("<!DOCTYPE svg"))
+        && text.contains("<svg")
+}
+
+/// Classifies a file by its content. Unreadable files and partial downloads
+/// are `Other`; the extension is never trusted for the format itself.
+pub fn detect(path: &Path) -> MediaType {
+    let partial = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| PARTIAL_EXTENSIONS.contains(&e.to_lowercase().as_str()));
+    if partial {
+        return MediaType::OTHER;
+    }
+
+    let Ok(mut file) = File::open(path) else { return MediaType::OTHER };
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    if file.by_ref().take(SNIFF_LEN as u64).read_to_end(&mut head).is_err() {
+        return MediaType::OTHER;
+    }
+
+    let format = match sniff(&head) {
+        Some(MediaFormat::Tiff) if raw::is_raw(&mut file) => MediaFormat::Raw,
+        Some(format) => format,
+        None => return MediaType::OTHER,
+    };
+    if !format.is_decodable() {
+        return MediaType { kind: MediaKind::Unsupported, format: Some(format) };
+    }
+
+    let animated = match format {
+        MediaFormat::Gif => gif_is_animated(&mut file).unwrap_or(false),
+        MediaFormat::Png => png_is_animated(&mut file).unwrap_or(false),
+        MediaFormat::WebP => webp_is_animated(&head),
+        _ => false,
+    };
+    let kind = if animated { MediaKind::AnimatedImage } else { MediaKind::Image };
+    MediaType { kind, format: Some(format) }
+}
+
+// ── decoding ──────────────────────────────────────────────────────────────────
+
+/// Pixels exactly as stored, already turned upright, plus the embedded ICC
+/// profile if any. Callers that shrink the image first can defer the colour
+/// conversion to the smaller buffer with `to_srgb`.
+pub struct DecodedImage {
+    pub image: DynamicImage,
+    pub icc_profile: Option<Vec<u8>>,
+    pub source: DecodeSource,
+}
+
+/// Where the pixels of a `DecodedImage` came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeSource {
+    Full,
+    /// JPEG decoded through a reduced IDCT at 1/n of its size.
+    Scaled(u8),
+    /// The JPEG preview stored in the EXIF block.
+    ExifThumbnail,
+    /// Not decoded at all: the placeholder drawn for a format this build
+    /// has no decoder for.
+    FallbackIcon,
+}
+
+/// The size a caller will scale a decoded image down to, in display
+/// orientation. `cover` means the image must fill the box (it gets cropped)
+/// rather than fit inside it.
+#[derive(Debug, Clone, Copy)]
+pub struct DecodeTarget {
+    pub width: u32,
+    pub height: u32,
+    pub cover: bool,
+}
+
+impl DecodeTarget {
+    // Smallest source size that still yields the same output; never more
+    // than the source itself.
+    fn required(&self, src_w: u32, src_h: u32) -> (u32, u32) {
+        let fx = self.width as f64 / src_w as f64;
+        let fy = self.height as f64 / src_h as f64;
+        let f = if self.cover { fx.max(fy) } else { fx.min(fy) }.min(1.0);
+        ((src_w as f64 * f).ceil() as u32, (src_h as f64 * f).ceil() as u32)
+    }
+}
+
+/// Decodes an image using the format sniffed from its content, so a PNG
+/// saved as `.jpg` still opens, and applies its EXIF orientation.
+/// Fails for anything `detect` can't decode.
+pub fn decode_image(path: &Path) -> Result<DecodedImage, Box<dyn std::error::Error + Send + Sync>> {
+    decode_limited(path, image::Limits::default())
+}
+
+/// Like `decode_image`, with room for images past the decoders' default
+/// 512 MiB allocation cap (about 170 megapixels of RGB). Only for callers
+/// that are built to hold such an image.
+pub fn decode_large_image(path: &Path) -> Result<DecodedImage, Box<dyn std::error::Error + Send + Sync>> {
+    let mut limits = image::Limits::default();
+    limits.max_alloc = Some(LARGE_IMAGE_ALLOC);
+    decode_limited(path, limits)
+}
+
+fn decode_limited(
+    path: &Path,
+    limits: image::Limits,
+) -> Result<DecodedImage, Box<dyn std::error::Error + Send + Sync>> {
+    let format = detect(path)
+        .format
+        .filter(MediaFormat::is_decodable)
+        .ok_or_else(|| format!("Unsupported media type: {}", path.display()))?;
+    let Some(image_format) = format.image_format() else {
+        return codecs::decode(path, format, None);
+    };
+
+    let mut reader = image::ImageReader::with_format(BufReader::new(File::open(path)?), image_format);
+    reader.limits(limits);
+    decode_with(reader.into_decoder()?)
+}
+
+/// Reads every pixel out of `decoder`, applying its orientation.
+pub(crate) fn decode_with(
+    mut decoder: impl ImageDecoder,
+) -> Result<DecodedImage, Box<dyn std::error::Error + Send + Sync>> {
+    // Both are best-effort: broken metadata shouldn't make the pixels unreadable
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    let icc_profile = decoder.icc_profile().ok().flatten();
+
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+    Ok(DecodedImage { image, icc_profile, source: DecodeSource::Full })
+}
+
+/// Like `decode_image`, but large JPEGs are only decoded as far as `target`
+/// needs: from the EXIF preview when that is big enough, otherwise at 1/2,
+/// 1/4 or 1/8 scale straight out of the IDCT; camera RAWs get the same
+/// treatment for their embedded preview. SVGs are rasterised at the target
+/// size. Everything else, and any JPEG the reduced path can't handle, gets
+/// a full decode.
+pub fn decode_image_for(
+    path: &Path,
+    target: DecodeTarget,
+) -> Result<DecodedImage, Box<dyn std::error::Error + Send + Sync>> {
+    match detect(path).format {
+        Some(MediaFormat::Jpeg) => {
+            if let Ok(Some(decoded)) = decode_jpeg_reduced(BufReader::new(File::open(path)?), target, None) {
+                return Ok(decoded);
+            }
+        }
+        Some(format @ (MediaFormat::Svg | MediaFormat::Raw)) if codecs::supports(format) => {
+            return codecs::decode(path, format, Some(target));
+        }
+        _ => {}
+    }
+    decode_image(path)
+}
+
+/// Pixel size in display orientation, read from the header without decoding.
+pub fn dimensions(path: &Path) -> Result<(u32, u32), Box<dyn std::error::Error + Send + Sync>> {
+    let format = detect(path)
+        .format
+        .filter(MediaFormat::is_decodable)
+        .ok_or_else(|| format!("Unsupported media type: {}", path.display()))?;
+    let Some(image_format) = format.image_format() else {
+        return codecs::dimensions(path, format);
+    };
+
+    let reader = image::ImageReader::with_format(BufReader::new(File::open(path)?), image_format);
+    let mut decoder = reader.into_decoder()?;
+    let (w, h) = decoder.dimensions();
+    let transposed = matches!(
+        decoder.orientation().unwrap_or(Orientation::NoTransforms),
+        Orientation::Rotate90 | Orientation::Rotate270 | Orientation::Rotate90FlipH | Orientation::Rotate270FlipH
+    );
+    Ok(if transposed { (h, w) } else { (w, h) })
+}
+
+/// Decodes an image upright and in sRGB.
+pub fn open_image(path: &Path) -> Result<DynamicImage, Box<dyn std::error::Error + Send + Sync>> {
+    let DecodedImage { mut image, icc_profile, .. } = decode_image(path)?;
+    if let Some(icc) = icc_profile {
+        to_srgb(&mut image, &icc);
+    }
+    Ok(image)
+}
+
+/// Converts pixels tagged with `icc` to sRGB. Grayscale, CMYK and
+/// unparseable profiles are left alone, as are profiles that already match
+/// sRGB. High bit depth images come back as 8-bit.
+pub fn to_srgb(image: &mut DynamicImage, icc: &[u8]) {
+    let Ok(source) = ColorProfile::new_from_slice(icc) else { return };
+    if source.color_space != DataColorSpace::Rgb || matches_srgb(&source) {
+        return;
+    }
+
+    let (w, h) = (image.width(), image.height());
+    let alpha = image.color().has_alpha();
+    let (layout, src) = if alpha {
+        (Layout::Rgba, image.to_rgba8().into_raw())
+    } else {
+        (Layout::Rgb, image.to_rgb8().into_raw())
+    };
+
+    let srgb = ColorProfile::new_srgb();
+    let Ok(transform) = source.create_transform_8bit(layout, &srgb, layout, TransformOptions::default()) else {
+        return;
+    };
+    let mut dst = vec![0u8; src.len()];
+    if transform.transform(&src, &mut dst).is_err() {
+        return;
+    }
+
+    let converted = if alpha {
+        RgbaImage::from_raw(w, h, dst).map(DynamicImage::ImageRgba8)
+    } else {
+        RgbImage::from_raw(w, h, dst).map(DynamicImage::ImageRgb8)
+    };
+    if let Some(converted) = converted {
+        *image = converted;
+    }
+}
+
+// ── reduced JPEG decoding ─────────────────────────────────────────────────────
+
+/// Decodes an in-memory JPEG, reduced for `target` when given.
+/// `orientation` takes precedence over the JPEG's own EXIF, for previews
+/// whose container knows better.
+pub(crate) fn decode_jpeg_bytes(
+    jpeg: &[u8],
+    target: Option<DecodeTarget>,
+    orientation: Option<Orientation>,
+) -> Result<DecodedImage, Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(target) = target {
+        if let Ok(Some(decoded)) = decode_jpeg_reduced(Cursor::new(jpeg), target, orientation) {
+            return Ok(decoded);
+        }
+    }
+
+    let mut decoder = image::codecs::jpeg::JpegDecoder::new(Cursor::new(jpeg))?;
+    let orientation = match orientation {
+        Some(orientation) => orientation,
+        None => decoder.orientation().unwrap_or(Orientation::NoTransforms),
+    };
+    let icc_profile = decoder.icc_profile().ok().flatten();
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+    Ok(DecodedImage { image, icc_profile, source: DecodeSource::Full })
+}
+
+// Ok(None) means a full decode is needed: the target is too close to the
+// source size, or the pixel format (CMYK, 16-bit lossless) isn't handled here.
+fn decode_jpeg_reduced(
+    reader: impl Read,
+    target: DecodeTarget,
+    orientation: Option<Orientation>,
+) -> Result<Option<DecodedImage>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut decoder = jpeg_decoder::Decoder::new(reader);
+    decoder.read_info()?;
+    let info = decoder.info().ok_or("Missing JPEG frame header")?;
+    if !matches!(info.pixel_format, jpeg_decoder::PixelFormat::L8 | jpeg_decoder::PixelFormat::RGB24) {
+        return Ok(None);
+    }
+
+    // APP markers precede the frame header, so read_info has already seen them
+    let exif = decoder.exif_data().map(<[u8]>::to_vec);
+    let orientation = orientation
+        .or_else(|| exif.as_deref().and_then(Orientation::from_exif_chunk))
+        .unwrap_or(Orientation::NoTransforms);
+    let icc_profile = decoder.icc_profile();
+
+    // Work in stored orientation; the target is given in display orientation
+    let (w, h) = (info.width as u32, info.height as u32);
+    let transposed = matches!(
+        orientation,
+        Orientation::Rotate90 | Orientation::Rotate270 | Orientation::Rotate90FlipH | Orientation::Rotate270FlipH
+    );
+    let (need_w, need_h) = if transposed {
+        let (nh, nw) = target.required(h, w);
+        (nw, nh)
+    } else {
+        target.required(w, h)
+    };
+
+    let (mut image, source) = if let Some(thumb) = exif.as_deref().and_then(|e| exif_thumbnail(e, w, h, need_w, need_h)) {
+        (thumb, DecodeSource::ExifThumbnail)
+    } else {
+        // IDCT sizes of 1, 2 and 4 (of 8) give 1/8, 1/4 and 1/2 scale
+        let scaled = |len: u32, idct: u32| (len * idct).div_ceil(8);
+        let Some(idct) = [1, 2, 4].into_iter().find(|&n| scaled(w, n) >= need_w && scaled(h, n) >= need_h) else {
+            return Ok(None);
+        };
+        // The decoder accepts a scale when either axis is big enough, so
+        // request by width alone with the scale already chosen for both
+        decoder.scale(scaled(w, idct) as u16, u16::MAX)?;
+        let pixels = decoder.decode()?;
+        let info = decoder.info().ok_or("Missing JPEG frame header")?;
+        let (sw, sh) = (info.width as u32, info.height as u32);
+        let image = match info.pixel_format {
+            jpeg_decoder::PixelFormat::L8 => GrayImage::from_raw(sw, sh, pixels).map(DynamicImage::ImageLuma8),
+            _ => RgbImage::from_raw(sw, sh, pixels).map(DynamicImage::ImageRgb8),
+        };
+        let image = image.ok_or("Decoded JPEG has the wrong buffer size")?;
+        (image, DecodeSource::Scaled((8 / idct) as u8))
+    };
+
+    image.apply_orientation(orientation);
+    Ok(Some(DecodedImage { image, icc_profile, source }))
+}
+
+// The IFD1 preview, if it is at least `need_w`x`need_h` and has the same
+// shape as the main image; many cameras letterbox it into a fixed 160x120.
+fn exif_thumbnail(exif: &[u8], src_w: u32, src_h: u32, need_w: u32, need_h: u32) -> Option<DynamicImage> {
+    use exif::{In, Tag};
+
+    let exif = exif::Reader::new().read_raw(exif.to_vec()).ok()?;
+    let field = |tag| exif.get_field(tag, In::THUMBNAIL).and_then(|f| f.value.get_uint(0));
+    let offset = field(Tag::JPEGInterchangeFormat)? as usize;
+    let len = field(Tag::JPEGInterchangeFormatLength)? as usize;
+    let bytes = exif.buf().get(offset..offset.checked_add(len)?)?;
+
+    let reader = image::ImageReader::with_format(Cursor::new(bytes), image::ImageFormat::Jpeg);
+    let (tw, th) = reader.into_dimensions().ok()?;
+    let src_aspect = src_w as f64 / src_h as f64;
+    let same_shape = ((tw as f64 / th as f64) - src_aspect).abs() / src_aspect < 0.01;
+    if tw < need_w || th < need_h || !same_shape {
+        return None;
+    }
+    image::load_from_memory_with_format(bytes, image::ImageFormat::Jpeg).ok()
+}
+
+// Most cameras and editors embed a plain sRGB profile; skip the no-op transform.
+fn matches_srgb(profile: &ColorProfile) -> bool {
+    const TOLERANCE: f64 = 0.002;
+    let srgb = ColorProfile::new_srgb();
+    let close = |a: &moxcms::Xyzd, b: &moxcms::Xyzd| {
+        (a.x - b.x).abs() < TOLERANCE && (a.y - b.y).abs() < TOLERANCE && (a.z - b.z).abs() < TOLERANCE
+    };
+
+    profile.is_matrix_shaper()
+        && close(&profile.red_colorant, &srgb.red_colorant)
+        && close(&profile.green_colorant, &srgb.green_colorant)
+        && close(&profile.blue_colorant, &srgb.blue_colorant)
+}
+
+// ── animation probes ──────────────────────────────────────────────────────────
+
+// Walks GIF blocks without decoding, stopping at the second image descriptor.
+fn gif_is_animated(file: &mut File) -> io::Result<bool> {
+    let mut r = BufReader::new(file);
+    r.seek(SeekFrom::Start(10))?;
+    let packed = read_u8(&mut r)?;
+    r.seek_relative(2)?;
+    if packed & 0x80 != 0 {
+        r.seek_relative(3 * (1 << ((packed & 0x07) + 1)))?;
+    }
+
+    let mut frames = 0;
+    loop {
+        match read_u8(&mut r)? {
+            0x2C => {
+                frames += 1;
+                if frames > 1 {
+                    return Ok(true);
+                }
+                r.seek_relative(8)?;
+                let packed = read_u8(&mut r)?;
+                if packed & 0x80 != 0 {
+                    r.seek_relative(3 * (1 << ((packed & 0x07) + 1)))?;
+                }
+                r.seek_relative(1)?; // LZW minimum code size
+                skip_sub_blocks(&mut r)?;
+            }
+            0x21 => {
+                r.seek_relative(1)?; // extension label
+                skip_sub_blocks(&mut r)?;
+            }
+            _ => return Ok(false), // trailer or garbage
+        }
+    }
+}
+
+fn skip_sub_blocks(r: &mut BufReader<&mut File>) -> io::Result<()> {
+    loop {
+        let len = read_u8(r)?;
+        if len == 0 {
+            return Ok(());
+        }
+        r.seek_relative(len as i64)?;
+    }
+}
+
+// APNG declares itself with an acTL chunk ahead of the first IDAT.
+fn png_is_animated(file: &mut File) -> io::Result<bool> {
+    let mut r = BufReader::new(file);
+    r.seek(SeekFrom::Start(8))?;
+    loop {
+        let mut header = [0u8; 8];
+        r.read_exact(&mut header)?;
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as i64;
+        match &header[4..8] {
+            b"acTL" => return Ok(true),
+            b"IDAT" | b"IEND" => return Ok(false),
+            _ => r.seek_relative(len + 4)?, // chunk data + CRC
+        }
+    }
+}
+
+// Extended WebP carries an animation flag in the VP8X header.
+fn webp_is_animated(head: &[u8]) -> bool {
+    head.len() > 20 && &head[12..16] == b"VP8X" && head[20] & 0x02 != 0
+}
+
+fn read_u8<R: Read>(r: &mut R) -> io::Result<u8> {
+    let mut b = [0u8; 1];
+    r.read_exact(&mut b)?;
+    Ok(b[0])
+}
@@ -1,6 +1,7 @@
 use crate::modules::config::get_config;
 use tauri::Emitter;
 use crate::modules::db::connect_db;
+use crate::modules::mediatype;
 use crate::log_info;
 use chrono::Utc;
 use dashmap::DashMap;
@@ -122,7 +123,7 @@ pub async fn export_category_pack(
     let entries: Vec<_> = fs::read_dir(&category_dir)
         .map_err(|e| e.to_string())?
         .filter_map(|e| e.ok())
-        .filter(|e| e.path().is_file())
+        .filter(|e| e.path().is_file() && mediatype::detect(&e.path()).is_media())
         .collect();
 
     let total = entries.len();
@@ -0,0 +1,46 @@
+use crate::modules::mediatype::{self, DecodeTarget};
+use image::GenericImageView;
+use std::path::Path;
+
+// Placeholders are BlurHash strings: a few cosine components, base83
+// encoded into 20-30 characters, which the grid can paint as a blurred
+// preview before the real thumbnail arrives. They are computed once per
+// file version by the background index pass and stored next to the
+// image's display size.
+
+// Largest side of the copy the hash is computed from; the components
+// only capture large-scale colour, so more pixels just cost time.
+const SAMPLE_SIZE: u32 = 32;
+
+// Components along the long and short side of the image.
+const LONG_COMPONENTS: u32 = 4;
+const SHORT_COMPONENTS: u32 = 3;
+
+pub struct Placeholder {
+    pub blurhash: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Computes the placeholder and display size for an image file.
+pub fn compute(path: &Path) -> Result<Placeholder, Box<dyn std::error::Error + Send + Sync>> {
+    let (width, height) = mediatype::dimensions(path)?;
+
+    let target = DecodeTarget { width: SAMPLE_SIZE, height: SAMPLE_SIZE, cover: false };
+    let decoded = mediatype::decode_image_for(path, target)?;
+    let mut sample = decoded.image.thumbnail(SAMPLE_SIZE, SAMPLE_SIZE);
+    if let Some(icc) = &decoded.icc_profile {
+        mediatype::to_srgb(&mut sample, icc);
+    }
+
+    let (w, h) = sample.dimensions();
+    let (cx, cy) = if w >= h {
+        (LONG_COMPONENTS, SHORT_COMPONENTS)
+    } else {
+        (SHORT_COMPONENTS, LONG_COMPONENTS)
+    };
+    let blurhash = blurhash::encode(cx, cy, w, h, sample.to_rgba8().as_raw())
+        .map_err(|e| format!("Failed to encode placeholder: {}", e))?;
+
+    Ok(Placeholder { blurhash, width, height })
+}
@@ -0,0 +1,216 @@
+use crate::modules::db::get_image_path_by_id;
+use crate::modules::deepzoom;
+use crate::modules::imgoptimize::{load_keyed, thumb_spec};
+use crate::modules::mediatype;
+use crate::modules::thumbcache;
+use crate::log_error;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tauri::http::{header, Request, Response, StatusCode};
+use tauri::UriSchemeResponder;
+
+// Serves raw image bytes to the webview so `<img>` tags can load thumbnails
+// directly instead of going through base64 over IPC:
+//
+//     lockerz://thumb/<image id>?p=<profile>&w=<width>&h=<height>&q=<quality>&fmt=<format>&bg=<background>&fit=<fit>&view=<study view>
+//
+// On Windows and Android the webview exposes this as
+// `http://lockerz.localhost/thumb/<id>`. Missing parameters come from the
+// named profile, or without one from the thumbnail settings in `Config`.
+//
+// Deep-zoom pyramids for the viewer live under `lockerz://dzi/`; see
+// `deepzoom` for the layout.
+
+pub const SCHEME: &str = "lockerz";
+
+// The URL names an image, not its contents, so the browser must revalidate;
+// the ETag (the content-addressed cache key) makes that a cheap 304.
+const CACHE_CONTROL: &str = "no-cache";
+
+/// Entry point for `register_asynchronous_uri_scheme_protocol`. Decoding
+/// runs on the blocking pool so the webview's request thread is never held.
+pub fn handle_lockerz_protocol(request: Request<Vec<u8>>, responder: UriSchemeResponder) {
+    tauri::async_runtime::spawn_blocking(move || {
+        let response = route(&request).unwrap_or_else(|(status, message)| {
+            if status == StatusCode::INTERNAL_SERVER_ERROR {
+                log_error!("{} {}: {}", SCHEME, request.uri(), message);
+            }
+            plain(status, message)
+        });
+        responder.respond(response);
+    });
+}
+
+type RouteError = (StatusCode, String);
+
+fn route(request: &Request<Vec<u8>>) -> Result<Response<Vec<u8>>, RouteError> {
+    let path = request.uri().path().trim_start_matches('/');
+    let segments: Vec<&str> = path.split('/').collect();
+    let no_route = || (StatusCode::NOT_FOUND, format!("No route for {}", path));
+
+    match segments.as_slice() {
+        ["thumb", id] => serve_thumbnail(request, parse_id(id)?),
+        ["dzi", name] => {
+            let id = name.strip_suffix(".dzi").ok_or_else(no_route)?;
+            serve_descriptor(request, parse_id(id)?)
+        }
+        ["dzi", files, level, tile] => {
+            let id = files.strip_suffix("_files").ok_or_else(no_route)?;
+            // The extension is whatever the descriptor said; the tile's own
+            // type comes from its bytes
+            let stem = tile.split_once('.').map_or(*tile, |(stem, _)| stem);
+            let (col, row) = stem.split_once('_').ok_or_else(no_route)?;
+            let index = |s: &str| {
+                s.parse::<u32>()
+                    .map_err(|_| (StatusCode::BAD_REQUEST, format!("Invalid tile: {}/{}", level, tile)))
+            };
+            serve_tile(request, parse_id(id)?, index(level)?, index(col)?, index(row)?)
+        }
+        _ => Err(no_route()),
+    }
+}
+
+fn parse_id(id: &str) -> Result<i64, RouteError> {
+    id.parse::<i64>()
+        .map_err(|_| (StatusCode::BAD_REQUEST, format!("Invalid image id: {}", id)))
+}
+
+fn image_path(id: i64) -> Result<PathBuf, RouteError> {
+    get_image_path_by_id(id)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?
+        .filter(|p| p.is_file())
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("Image {} not found", id)))
+}
+
+fn serve_thumbnail(request: &Request<Vec<u8>>, id: i64) -> Result<Response<Vec<u8>>, RouteError> {
+    let params = query_params(request.uri().query().unwrap_or(""));
+    let text = |name: &str| params.get(name).map(String::as_str);
+    let param = |name: &str| text(name).and_then(|v| v.parse::<i32>().ok());
+    let spec = thumb_spec(
+        text("p"),
+        param("w"),
+        param("h"),
+        param("q"),
+        text("fmt"),
+        text("bg"),
+        text("fit"),
+        text("view"),
+    )
+    .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    let path = image_path(id)?;
+    let key = thumbcache::key_for(&path, &spec)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    cached(request, key.as_str(), || {
+        let bytes = load_keyed(&path.to_string_lossy(), key.clone(), &spec)?;
+        Ok((content_type(&bytes), bytes))
+    })
+}
+
+fn serve_descriptor(request: &Request<Vec<u8>>, id: i64) -> Result<Response<Vec<u8>>, RouteError> {
+    let path = image_path(id)?;
+    let (tag, xml) = deepzoom::descriptor(&path).map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e.to_string()))?;
+    cached(request, &tag, || Ok(("application/xml", xml.into_bytes())))
+}
+
+fn serve_tile(
+    request: &Request<Vec<u8>>,
+    id: i64,
+    level: u32,
+    col: u32,
+    row: u32,
+) -> Result<Response<Vec<u8>>, RouteError> {
+    let path = image_path(id)?;
+    let key = deepzoom::tile_key(&path, level, col, row)
+        .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e.to_string()))?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("No tile {}/{}_{} for image {}", level, col, row, id)))?;
+
+    cached(request, key.as_str(), || {
+        let bytes = deepzoom::load_tile(&path, &key)?;
+        Ok((content_type(&bytes), bytes))
+    })
+}
+
+// Answers a conditional request with 304 when `tag` still matches, and
+// otherwise with whatever `load` produces.
+fn cached(
+    request: &Request<Vec<u8>>,
+    tag: &str,
+    load: impl FnOnce() -> Result<(&'static str, Vec<u8>), Box<dyn std::error::Error + Send + Sync>>,
+) -> Result<Response<Vec<u8>>, RouteError> {
+    let etag = format!("\"{}\"", tag);
+    let not_modified = request
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.split(',').any(|tag| tag.trim() == etag));
+
+    let builder = Response::builder()
+        .header(header::ETAG, &etag)
+        .header(header::CACHE_CONTROL, CACHE_CONTROL)
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*");
+
+    if not_modified {
+        return builder
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Vec::new())
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
+    }
+
+    let (content_type, bytes) = load().map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    builder
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CONTENT_LENGTH, bytes.len())
+        .body(bytes)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+fn plain(status: StatusCode, message: String) -> Response<Vec<u8>> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .body(message.into_bytes())
+        .unwrap_or_default()
+}
+
+// `auto` thumbnails and tiles may be JPEG or WebP, so the type comes from the bytes.
+fn content_type(bytes: &[u8]) -> &'static str {
+    mediatype::sniff(bytes).map(|f| f.mime()).unwrap_or("application/octet-stream")
+}
+
+// Names are plain ASCII; values are decoded because `URLSearchParams`
+// escapes the commas in view lists.
+fn query_params(query: &str) -> HashMap<&str, String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(name, value)| (name, percent_decode(value)))
+        .collect()
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| value.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (escaped, bytes[i]) {
+            (Some(byte), _) => {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+            (None, b'+') => decoded.push(b' '),
+            (None, byte) => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
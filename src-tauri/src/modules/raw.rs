@@ -0,0 +1,299 @@
+use crate::modules::mediatype::{self, DecodeTarget, DecodedImage};
+use image::metadata::Orientation;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+// TIFF-based camera RAW: Canon CR2, Nikon NEF, Sony ARW and Adobe DNG.
+// Developing the sensor data would take a full RAW pipeline, but each of
+// these also carries JPEG previews rendered by the camera, the largest
+// usually at full resolution. Thumbnails, hashes and dimensions all come
+// from that preview; only the IFDs and the chosen JPEG are ever read.
+
+const TAG_COMPRESSION: u16 = 0x0103;
+const TAG_MAKE: u16 = 0x010F;
+const TAG_STRIP_OFFSETS: u16 = 0x0111;
+const TAG_ORIENTATION: u16 = 0x0112;
+const TAG_STRIP_BYTE_COUNTS: u16 = 0x0117;
+const TAG_SUB_IFDS: u16 = 0x014A;
+const TAG_JPEG_OFFSET: u16 = 0x0201;
+const TAG_JPEG_LENGTH: u16 = 0x0202;
+const TAG_DNG_VERSION: u16 = 0xC612;
+
+// Old-style and new-style JPEG compression
+const COMPRESSION_JPEG: [u32; 2] = [6, 7];
+
+// Damaged files can chain IFDs into loops
+const MAX_IFDS: usize = 32;
+
+// How much of a candidate is read to find its frame header; the EXIF block
+// in front of it is rarely larger.
+const JPEG_HEAD_LEN: u32 = 256 * 1024;
+
+// ── container ─────────────────────────────────────────────────────────────────
+
+struct Entry {
+    tag: u16,
+    kind: u16,
+    count: u32,
+    // The value itself when it fits in four bytes, otherwise its offset
+    value: [u8; 4],
+}
+
+struct Tiff<R> {
+    reader: R,
+    little_endian: bool,
+    first_ifd: u32,
+    canon: bool,
+}
+
+impl<R: Read + Seek> Tiff<R> {
+    fn open(mut reader: R) -> io::Result<Self> {
+        let mut header = [0u8; 10];
+        reader.seek(SeekFrom::Start(0))?;
+        reader.read_exact(&mut header)?;
+        let little_endian = match &header[..4] {
+            b"II*\0" => true,
+            b"MM\0*" => false,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a TIFF container")),
+        };
+        let mut tiff = Tiff { reader, little_endian, first_ifd: 0, canon: &header[8..10] == b"CR" };
+        tiff.first_ifd = tiff.u32(&header[4..8]);
+        Ok(tiff)
+    }
+
+    fn u16(&self, b: &[u8]) -> u16 {
+        let b = [b[0], b[1]];
+        if self.little_endian { u16::from_le_bytes(b) } else { u16::from_be_bytes(b) }
+    }
+
+    fn u32(&self, b: &[u8]) -> u32 {
+        let b = [b[0], b[1], b[2], b[3]];
+        if self.little_endian { u32::from_le_bytes(b) } else { u32::from_be_bytes(b) }
+    }
+
+    /// The entries of the IFD at `offset` and the offset of the next one.
+    fn ifd(&mut self, offset: u32) -> io::Result<(Vec<Entry>, u32)> {
+        self.reader.seek(SeekFrom::Start(offset as u64))?;
+        let mut count = [0u8; 2];
+        self.reader.read_exact(&mut count)?;
+        let mut raw = vec![0u8; self.u16(&count) as usize * 12 + 4];
+        self.reader.read_exact(&mut raw)?;
+
+        let (entries, next) = raw.split_at(raw.len() - 4);
+        let entries = entries
+            .chunks_exact(12)
+            .map(|e| Entry {
+                tag: self.u16(&e[0..2]),
+                kind: self.u16(&e[2..4]),
+                count: self.u32(&e[4..8]),
+                value: [e[8], e[9], e[10], e[11]],
+            })
+            .collect();
+        Ok((entries, self.u32(next)))
+    }
+
+    /// Unsigned integer values (SHORT, LONG or IFD) of an entry.
+    fn uints(&mut self, entry: &Entry) -> io::Result<Vec<u32>> {
+        let size = match entry.kind {
+            3 => 2,
+            4 | 13 => 4,
+            _ => return Ok(Vec::new()),
+        };
+        let len = entry.count.min(1024) as usize * size;
+        let bytes = if len <= 4 {
+            entry.value[..len].to_vec()
+        } else {
+            let mut bytes = vec![0u8; len];
+            self.reader.seek(SeekFrom::Start(self.u32(&entry.value) as u64))?;
+            self.reader.read_exact(&mut bytes)?;
+            bytes
+        };
+        Ok(bytes
+            .chunks_exact(size)
+            .map(|b| if size == 2 { self.u16(b) as u32 } else { self.u32(b) })
+            .collect())
+    }
+
+    fn uint(&mut self, entries: &[Entry], tag: u16) -> Option<u32> {
+        let entry = entries.iter().find(|e| e.tag == tag)?;
+        self.uints(entry).ok()?.first().copied()
+    }
+
+    fn ascii(&mut self, entries: &[Entry], tag: u16) -> Option<String> {
+        let entry = entries.iter().find(|e| e.tag == tag && e.kind == 2)?;
+        let len = entry.count.min(256) as usize;
+        let bytes = if len <= 4 {
+            entry.value[..len].to_vec()
+        } else {
+            let mut bytes = vec![0u8; len];
+            self.reader.seek(SeekFrom::Start(self.u32(&entry.value) as u64)).ok()?;
+            self.reader.read_exact(&mut bytes).ok()?;
+            bytes
+        };
+        Some(String::from_utf8_lossy(&bytes).trim_end_matches('\0').trim().to_string())
+    }
+
+    fn read_at(&mut self, offset: u32, len: u32) -> io::Result<Vec<u8>> {
+        let mut bytes = vec![0u8; len as usize];
+        self.reader.seek(SeekFrom::Start(offset as u64))?;
+        self.reader.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+/// Whether a TIFF container is one of the supported RAW formats rather
+/// than a plain TIFF. Nikon and Sony don't mark their files, so those are
+/// recognised by maker plus the preview pointers every NEF and ARW has.
+pub fn is_raw(file: &mut File) -> bool {
+    let Ok(mut tiff) = Tiff::open(BufReader::new(file)) else { return false };
+    if tiff.canon {
+        return true;
+    }
+    let Ok((entries, _)) = tiff.ifd(tiff.first_ifd) else { return false };
+    if entries.iter().any(|e| e.tag == TAG_DNG_VERSION) {
+        return true;
+    }
+    let camera = tiff
+        .ascii(&entries, TAG_MAKE)
+        .is_some_and(|make| make.to_uppercase().starts_with("NIKON") || make.to_uppercase().starts_with("SONY"));
+    camera && entries.iter().any(|e| e.tag == TAG_SUB_IFDS || e.tag == TAG_JPEG_OFFSET)
+}
+
+// ── previews ──────────────────────────────────────────────────────────────────
+
+/// Where the largest embedded preview sits in the file.
+pub struct Preview {
+    offset: u32,
+    length: u32,
+    /// Stored size of the JPEG, before `orientation`.
+    pub width: u32,
+    pub height: u32,
+    /// From the RAW's IFD0; the previews themselves rarely carry one.
+    pub orientation: Orientation,
+}
+
+/// Finds the largest baseline or progressive JPEG among IFD0, its chain and
+/// their SubIFDs. Lossless JPEG streams (the sensor data in CR2 and DNG)
+/// are skipped.
+pub fn find_preview(path: &Path) -> Result<Preview, Box<dyn std::error::Error + Send + Sync>> {
+    let mut tiff = Tiff::open(BufReader::new(File::open(path)?))?;
+    let mut pending = vec![tiff.first_ifd];
+    let mut visited = Vec::new();
+    let mut orientation = None;
+    let mut best: Option<(u32, u32, u32, u32)> = None; // offset, length, width, height
+
+    while let Some(offset) = pending.pop() {
+        if offset == 0 || visited.contains(&offset) || visited.len() >= MAX_IFDS {
+            continue;
+        }
+        visited.push(offset);
+        let Ok((entries, next)) = tiff.ifd(offset) else { continue };
+        pending.push(next);
+        if let Some(sub_ifds) = entries.iter().find(|e| e.tag == TAG_SUB_IFDS) {
+            pending.extend(tiff.uints(sub_ifds).unwrap_or_default());
+        }
+        if offset == tiff.first_ifd {
+            orientation = tiff.uint(&entries, TAG_ORIENTATION);
+        }
+
+        for (start, len) in candidates(&mut tiff, &entries) {
+            let Ok(head) = tiff.read_at(start, len.min(JPEG_HEAD_LEN)) else { continue };
+            let Some((w, h)) = jpeg_size(&head) else { continue };
+            if !best.is_some_and(|(_, _, bw, bh)| w as u64 * h as u64 <= bw as u64 * bh as u64) {
+                best = Some((start, len, w, h));
+            }
+        }
+    }
+
+    let (offset, length, width, height) = best.ok_or("No embedded JPEG preview")?;
+    Ok(Preview {
+        offset,
+        length,
+        width,
+        height,
+        orientation: orientation
+            .and_then(|o| Orientation::from_exif(o as u8))
+            .unwrap_or(Orientation::NoTransforms),
+    })
+}
+
+impl Preview {
+    /// The encoded JPEG.
+    pub fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let mut file = File::open(path)?;
+        let mut jpeg = vec![0u8; self.length as usize];
+        file.seek(SeekFrom::Start(self.offset as u64))?;
+        file.read_exact(&mut jpeg)?;
+        Ok(jpeg)
+    }
+}
+
+// (offset, length) of every JPEG an IFD points at: the JPEGInterchangeFormat
+// pair, or a single JPEG-compressed strip.
+fn candidates<R: Read + Seek>(tiff: &mut Tiff<R>, entries: &[Entry]) -> Vec<(u32, u32)> {
+    let mut found = Vec::new();
+    if let (Some(start), Some(len)) = (tiff.uint(entries, TAG_JPEG_OFFSET), tiff.uint(entries, TAG_JPEG_LENGTH)) {
+        found.push((start, len));
+    }
+    let jpeg = tiff.uint(entries, TAG_COMPRESSION).is_some_and(|c| COMPRESSION_JPEG.contains(&c));
+    let strips = entries.iter().find(|e| e.tag == TAG_STRIP_OFFSETS).map(|e| e.count);
+    if jpeg && strips == Some(1) {
+        if let (Some(start), Some(len)) = (tiff.uint(entries, TAG_STRIP_OFFSETS), tiff.uint(entries, TAG_STRIP_BYTE_COUNTS)) {
+            found.push((start, len));
+        }
+    }
+    found.retain(|&(_, len)| len > 0);
+    found
+}
+
+// Size from the frame header, for baseline (SOF0/1) and progressive (SOF2)
+// JPEGs only.
+fn jpeg_size(jpeg: &[u8]) -> Option<(u32, u32)> {
+    if !jpeg.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut i = 2;
+    while i + 9 <= jpeg.len() {
+        if jpeg[i] != 0xFF {
+            return None;
+        }
+        let marker = jpeg[i + 1];
+        if marker == 0xFF {
+            i += 1; // fill byte
+            continue;
+        }
+        let len = u16::from_be_bytes([jpeg[i + 2], jpeg[i + 3]]) as usize;
+        match marker {
+            0xC0..=0xC2 => {
+                let h = u16::from_be_bytes([jpeg[i + 5], jpeg[i + 6]]) as u32;
+                let w = u16::from_be_bytes([jpeg[i + 7], jpeg[i + 8]]) as u32;
+                return (w > 0 && h > 0).then_some((w, h));
+            }
+            // Lossless, hierarchical and arithmetic-coded frames
+            0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF => return None,
+            _ => i += 2 + len,
+        }
+    }
+    None
+}
+
+/// Decodes the preview upright; large previews are decoded only as far as
+/// `target` needs, like JPEG files are.
+pub fn decode(
+    path: &Path,
+    target: Option<DecodeTarget>,
+) -> Result<DecodedImage, Box<dyn std::error::Error + Send + Sync>> {
+    let preview = find_preview(path)?;
+    mediatype::decode_jpeg_bytes(&preview.read(path)?, target, Some(preview.orientation))
+}
+
+/// Size of the preview in display orientation.
+pub fn dimensions(path: &Path) -> Result<(u32, u32), Box<dyn std::error::Error + Send + Sync>> {
+    let preview = find_preview(path)?;
+    let transposed = matches!(
+        preview.orientation,
+        Orientation::Rotate90 | Orientation::Rotate270 | Orientation::Rotate90FlipH | Orientation::Rotate270FlipH
+    );
+    Ok(if transposed { (preview.height, preview.width) } else { (preview.width, preview.height) })
+}
@@ -1,7 +1,7 @@
 use crate::modules::config::get_config;
+use crate::modules::filecache::{get_or_init_cache, list_category_dirs};
+use crate::modules::pathutils::get_main_path;
 use serde::Serialize;
-use std::fs;
-use std::path::Path;
 
 #[derive(Serialize)]
 pub struct StatsResponse {
@@ -10,29 +10,6 @@ pub struct StatsResponse {
     pub storage_used: u64,
 }
 
-fn get_dir_stats(path: &Path) -> Result<(u64, u64), String> {
-    let mut total_size = 0;
-    let mut count = 0;
-
-    for entry in fs::read_dir(path).map_err(|e| format!("Error reading directory: {}", e))? {
-        let entry = entry.map_err(|e| format!("Error reading entry: {}", e))?;
-        let entry_path = entry.path();
-
-        if entry_path.is_dir() {
-            let (subdir_size, subdir_count) = get_dir_stats(&entry_path)?;
-            total_size += subdir_size;
-            count += subdir_count;
-        } else if entry_path.is_file() {
-            let metadata = fs::metadata(&entry_path)
-                .map_err(|e| format!("Error reading metadata: {}", e))?;
-            total_size += metadata.len();
-            count += 1;
-        }
-    }
-
-    Ok((total_size, count))
-}
-
 #[tauri::command]
 pub async fn get_stats() -> Result<StatsResponse, String> {
     let root_folder_path = get_config().folderPath;
@@ -41,22 +18,21 @@ pub async fn get_stats() -> Result<StatsResponse, String> {
         return Err("Root folder path does not exist.".to_string());
     }
 
-    let (size, count) = get_dir_stats(&root_folder_path)?;
+    let main_path = get_main_path().map_err(|e| format!("Failed to get main path: {}", e))?;
+    let cache = get_or_init_cache(main_path.join("cache"))
+        .map_err(|e| format!("Failed to get cache: {}", e))?;
+
+    let totals = cache
+        .category_totals(&root_folder_path)
+        .map_err(|e| format!("Error reading index totals: {}", e))?;
 
-    let categories_count = fs::read_dir(&root_folder_path)
+    let categories_count = list_category_dirs(&root_folder_path)
         .map_err(|e| format!("Error reading root folder: {}", e))?
-        .filter(|entry| match entry {
-            Ok(entry) => {
-                entry.file_type().map(|ft| ft.is_dir()).unwrap_or(false)
-                    && entry.file_name() != "temp"
-            }
-            Err(_) => false,
-        })
-        .count();
+        .len();
 
     Ok(StatsResponse {
-        total_images: count,
+        total_images: totals.values().map(|(count, _)| *count as u64).sum(),
         categories: categories_count as u32,
-        storage_used: size,
+        storage_used: totals.values().map(|(_, size)| *size).sum(),
     })
 }
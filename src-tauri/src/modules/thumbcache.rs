@@ -0,0 +1,678 @@
+use crate::modules::config::get_config;
+use crate::modules::filecache::content_hash;
+use crate::modules::mediatype::{self, MediaKind};
+use crate::modules::pathutils::get_main_path;
+use crate::{log_error, log_info};
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Thumbnails live under `cache/thumbs/<first two hex chars>/`, named after
+// the source file's content hash and the parameters that produced them:
+//
+//     <sha256>_<w>x<h>_q<quality>[_<fit>][_<background>][_<view>][_mirror][_anim|_icon].<format>
+//
+// `_anim` marks animated previews, which only WebP (and `auto`) can hold;
+// `_icon` marks the fallback icon of a format this build can't decode, so a
+// build that can decode it doesn't serve the icon. Deep-zoom tiles share
+// the store as `<sha256>_dz<tile size>_<level>_<col>_<row>.auto`.
+// Because the name is derived from the contents, an edited file simply maps
+// to new entries; the old ones age out through LRU eviction.
+
+const THUMB_DIR: &str = "thumbs";
+const ANIMATED_TOKEN: &str = "_anim";
+const ICON_TOKEN: &str = "_icon";
+const MB: u64 = 1024 * 1024;
+
+// Eviction trims down to this fraction of the budget so it doesn't run on every write.
+const EVICT_TARGET: f64 = 0.9;
+
+// Bumping an entry's mtime on every hit is wasted I/O; once a minute is plenty for LRU.
+const TOUCH_INTERVAL_MS: u64 = 60_000;
+
+static STORE: Lazy<ThumbStore> = Lazy::new(ThumbStore::open);
+static TMP_SEQ: AtomicU64 = AtomicU64::new(0);
+
+// Source path → (size, mtime, content hash, media kind), so warm hits skip
+// the DB and the header sniff.
+static SOURCE_HASHES: Lazy<DashMap<PathBuf, (u64, u64, String, MediaKind)>> = Lazy::new(DashMap::new);
+
+// ── keys ──────────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ThumbSpec {
+    pub width: u32,
+    pub height: u32,
+    pub quality: u8,
+    pub format: ThumbFormat,
+    pub background: Background,
+    pub fit: FitMode,
+    pub view: StudyView,
+}
+
+impl ThumbSpec {
+    /// Whether `key` was rendered with this spec on any background and in
+    /// any study view. A profile pins everything but those, so this is what
+    /// a profile change has to invalidate.
+    pub fn matches_any_variant(&self, key: &ThumbKey) -> bool {
+        let Some((_, rest)) = key.0.split_once('_') else { return false };
+        let head = format!("{}x{}_q{}{}", self.width, self.height, self.quality, self.fit.token());
+        let tail = format!(".{}", self.format.token());
+        let Some(middle) = rest.strip_prefix(head.as_str()).and_then(|r| r.strip_suffix(tail.as_str())) else {
+            return false;
+        };
+        let variants = middle
+            .strip_suffix(ANIMATED_TOKEN)
+            .or_else(|| middle.strip_suffix(ICON_TOKEN))
+            .unwrap_or(middle);
+        // The head has to end at a token boundary, or a contain-fit `q8`
+        // would also claim `q80` to `q89`
+        if !variants.is_empty() && !variants.starts_with('_') {
+            return false;
+        }
+        variants.split('_').skip(1).all(is_variant_token)
+    }
+}
+
+// Background and study-view tokens, the parts of a key a profile doesn't pin.
+fn is_variant_token(token: &str) -> bool {
+    let number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    matches!(token, "checker" | "gray" | "edges" | "mirror")
+        || token
+            .strip_prefix("bg")
+            .is_some_and(|hex| hex.len() == 6 && hex.bytes().all(|b| b.is_ascii_hexdigit()))
+        || token.strip_prefix("post").is_some_and(number)
+        || token.strip_prefix("notan").is_some_and(number)
+}
+
+/// Output encoding. `Auto` picks WebP when the thumbnail has transparent
+/// pixels and JPEG otherwise, so the same key may hold either; serve it
+/// with the type sniffed from the bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ThumbFormat {
+    #[default]
+    Auto,
+    Jpeg,
+    WebP,
+    WebPLossless,
+    Png,
+}
+
+/// How the source is mapped onto the requested box. `Contain` scales to fit
+/// inside it; the others fill it exactly, cropping what doesn't fit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum FitMode {
+    #[default]
+    Contain,
+    /// Centered crop to the box's aspect ratio.
+    Cover,
+    /// Centered square crop, sized to the box's shorter side.
+    Square,
+    /// Crop positioned on the most detailed part of the image.
+    Smart,
+}
+
+/// A derived view for studying a reference: at most one tone mapping, with
+/// or without a horizontal flip. Alpha is left as it is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct StudyView {
+    pub tone: Tone,
+    pub mirror: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Tone {
+    #[default]
+    Color,
+    /// Relative luminance, so each pixel keeps its value but loses its hue.
+    Grayscale,
+    /// Luminance reduced to this many evenly spaced values.
+    Posterize(u8),
+    /// Black below this luminance, white from it up.
+    Notan(u8),
+    /// Outlines only, dark on white.
+    Edges,
+}
+
+/// What transparent pixels are flattened onto. `None` keeps alpha when the
+/// output format can carry it and falls back to white for JPEG.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Background {
+    #[default]
+    None,
+    Checkerboard,
+    Color([u8; 3]),
+}
+
+impl ThumbFormat {
+    /// Whether animated sources keep moving in this format.
+    pub fn animates(&self) -> bool {
+        matches!(self, ThumbFormat::Auto | ThumbFormat::WebP | ThumbFormat::WebPLossless)
+    }
+
+    pub fn parse(s: &str) -> Option<ThumbFormat> {
+        match s.to_ascii_lowercase().as_str() {
+            "" | "auto" => Some(ThumbFormat::Auto),
+            "jpg" | "jpeg" => Some(ThumbFormat::Jpeg),
+            "webp" => Some(ThumbFormat::WebP),
+            "webp-lossless" => Some(ThumbFormat::WebPLossless),
+            "png" => Some(ThumbFormat::Png),
+            _ => None,
+        }
+    }
+
+    fn token(&self) -> &'static str {
+        match self {
+            ThumbFormat::Auto => "auto",
+            ThumbFormat::Jpeg => "jpg",
+            ThumbFormat::WebP => "webp",
+            ThumbFormat::WebPLossless => "webpll",
+            ThumbFormat::Png => "png",
+        }
+    }
+}
+
+impl StudyView {
+    /// Parses a comma-separated list like `grayscale`, `posterize-3,mirror`
+    /// or `notan-100`. Posterize takes 2-8 steps (default 4), notan a
+    /// threshold (default 128).
+    pub fn parse(s: &str) -> Option<StudyView> {
+        let mut view = StudyView::default();
+        for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let part = part.to_ascii_lowercase();
+            let (name, arg) = match part.split_once('-') {
+                Some((name, arg)) => (name, Some(arg.parse::<u8>().ok()?)),
+                None => (part.as_str(), None),
+            };
+            let tone = match (name, arg) {
+                ("mirror", None) => {
+                    view.mirror = true;
+                    continue;
+                }
+                ("color" | "none", None) => Tone::Color,
+                ("grayscale" | "gray", None) => Tone::Grayscale,
+                ("posterize", steps) => match steps.unwrap_or(4) {
+                    steps @ 2..=8 => Tone::Posterize(steps),
+                    _ => return None,
+                },
+                ("notan", threshold) => Tone::Notan(threshold.unwrap_or(128)),
+                ("edges", None) => Tone::Edges,
+                _ => return None,
+            };
+            // One tone mapping per view; they don't compose meaningfully
+            if view.tone != Tone::Color && view.tone != tone {
+                return None;
+            }
+            view.tone = tone;
+        }
+        Some(view)
+    }
+
+    fn token(&self) -> String {
+        let tone = match self.tone {
+            Tone::Color => String::new(),
+            Tone::Grayscale => "_gray".to_string(),
+            Tone::Posterize(steps) => format!("_post{}", steps),
+            Tone::Notan(threshold) => format!("_notan{}", threshold),
+            Tone::Edges => "_edges".to_string(),
+        };
+        if self.mirror {
+            tone + "_mirror"
+        } else {
+            tone
+        }
+    }
+}
+
+impl FitMode {
+    pub fn parse(s: &str) -> Option<FitMode> {
+        match s.to_ascii_lowercase().as_str() {
+            "" | "contain" => Some(FitMode::Contain),
+            "cover" => Some(FitMode::Cover),
+            "square" => Some(FitMode::Square),
+            "smart" => Some(FitMode::Smart),
+            _ => None,
+        }
+    }
+
+    fn token(&self) -> &'static str {
+        match self {
+            FitMode::Contain => "",
+            FitMode::Cover => "_cover",
+            FitMode::Square => "_square",
+            FitMode::Smart => "_smart",
+        }
+    }
+}
+
+impl Background {
+    /// Accepts `none`, `checkerboard`, or a hex colour with or without `#`.
+    pub fn parse(s: &str) -> Option<Background> {
+        let s = s.trim();
+        match s.to_ascii_lowercase().as_str() {
+            "" | "none" => return Some(Background::None),
+            "checkerboard" => return Some(Background::Checkerboard),
+            _ => {}
+        }
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        if hex.len() != 6 {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+        Some(Background::Color([channel(0)?, channel(2)?, channel(4)?]))
+    }
+
+    fn token(&self) -> String {
+        match self {
+            Background::None => String::new(),
+            Background::Checkerboard => "_checker".to_string(),
+            Background::Color([r, g, b]) => format!("_bg{:02x}{:02x}{:02x}", r, g, b),
+        }
+    }
+}
+
+/// Names one rendition of one source file's contents.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ThumbKey(String);
+
+impl ThumbKey {
+    pub fn new(hash: &str, spec: &ThumbSpec) -> ThumbKey {
+        ThumbKey::for_source(hash, spec, MediaKind::Image)
+    }
+
+    /// Key for a source of the given kind. Animations get a separate key in
+    /// formats that can hold one, and undecodable files one for their icon.
+    pub fn for_source(hash: &str, spec: &ThumbSpec, kind: MediaKind) -> ThumbKey {
+        let source = match kind {
+            MediaKind::AnimatedImage if spec.format.animates() => ANIMATED_TOKEN,
+            MediaKind::Unsupported => ICON_TOKEN,
+            _ => "",
+        };
+        ThumbKey(format!(
+            "{}_{}x{}_q{}{}{}{}{}.{}",
+            hash,
+            spec.width,
+            spec.height,
+            spec.quality,
+            spec.fit.token(),
+            spec.background.token(),
+            spec.view.token(),
+            source,
+            spec.format.token()
+        ))
+    }
+
+    /// Key for one tile of a source's deep-zoom pyramid.
+    pub fn tile(hash: &str, tile_size: u32, level: u32, col: u32, row: u32) -> ThumbKey {
+        ThumbKey(format!(
+            "{}_dz{}_{}_{}_{}.{}",
+            hash,
+            tile_size,
+            level,
+            col,
+            row,
+            ThumbFormat::Auto.token()
+        ))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    fn relative_path(&self) -> PathBuf {
+        Path::new(&self.0[..2.min(self.0.len())]).join(&self.0)
+    }
+}
+
+/// Resolves the cache key for `path` rendered with `spec`.
+pub fn key_for(path: &Path, spec: &ThumbSpec) -> io::Result<ThumbKey> {
+    let (hash, kind) = source_of(path)?;
+    Ok(ThumbKey::for_source(&hash, spec, kind))
+}
+
+/// Content hash and media kind of `path`, re-hashing the source only when
+/// its size or mtime changed since it was last seen.
+pub fn source_of(path: &Path) -> io::Result<(String, MediaKind)> {
+    let metadata = fs::metadata(path)?;
+    let size = metadata.len();
+    let modified = metadata.modified().map(system_time_ms)?;
+
+    if let Some(entry) = SOURCE_HASHES.get(path) {
+        let (s, m, hash, kind) = entry.value();
+        if *s == size && *m == modified {
+            return Ok((hash.clone(), *kind));
+        }
+    }
+
+    let hash = content_hash(path, &metadata)?;
+    let kind = mediatype::detect(path).kind;
+    SOURCE_HASHES.insert(path.to_path_buf(), (size, modified, hash.clone(), kind));
+    Ok((hash, kind))
+}
+
+/// Forgets remembered source hashes under `prefix` after files change or move.
+pub fn forget_sources(prefix: &str) {
+    SOURCE_HASHES.retain(|p, _| !p.to_string_lossy().starts_with(prefix));
+}
+
+// ── disk store ────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Serialize, Clone, Copy, Default)]
+pub struct DiskCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub writes: u64,
+    pub evictions: u64,
+    pub entries: usize,
+    pub bytes: u64,
+    pub budget_bytes: u64,
+}
+
+struct Entry {
+    bytes: u64,
+    last_access_ms: u64,
+}
+
+struct ThumbStore {
+    root: PathBuf,
+    budget: AtomicU64,
+    index: Mutex<StoreIndex>,
+}
+
+#[derive(Default)]
+struct StoreIndex {
+    entries: HashMap<String, Entry>,
+    total_bytes: u64,
+    hits: u64,
+    misses: u64,
+    writes: u64,
+    evictions: u64,
+    // Key prefixes eviction leaves alone, see `pin`
+    pinned: Vec<String>,
+}
+
+impl ThumbStore {
+    // File mtimes double as the persisted access times, so the LRU order
+    // survives restarts without a separate index file.
+    fn open() -> ThumbStore {
+        let root = get_main_path()
+            .map(|p| p.join("cache").join(THUMB_DIR))
+            .unwrap_or_else(|_| PathBuf::from("cache").join(THUMB_DIR));
+        if let Err(e) = fs::create_dir_all(&root) {
+            log_error!("Failed to create thumbnail cache {}: {}", root.display(), e);
+        }
+
+        let mut index = StoreIndex::default();
+        for shard in fs::read_dir(&root).into_iter().flatten().flatten() {
+            for file in fs::read_dir(shard.path()).into_iter().flatten().flatten() {
+                let name = file.file_name().to_string_lossy().to_string();
+                let Ok(metadata) = file.metadata() else { continue };
+                if !metadata.is_file() {
+                    continue;
+                }
+                if name.ends_with(".tmp") {
+                    let _ = fs::remove_file(file.path());
+                    continue;
+                }
+                let last_access_ms = metadata.modified().map(system_time_ms).unwrap_or(0);
+                index.total_bytes += metadata.len();
+                index.entries.insert(name, Entry { bytes: metadata.len(), last_access_ms });
+            }
+        }
+
+        log_info!(
+            "Thumbnail cache: {} entries, {:.1} MB",
+            index.entries.len(),
+            index.total_bytes as f64 / MB as f64
+        );
+
+        let store = ThumbStore {
+            root,
+            budget: AtomicU64::new(get_config().thumb_cache_mb * MB),
+            index: Mutex::new(index),
+        };
+        store.evict();
+        store
+    }
+
+    fn path_of(&self, key: &ThumbKey) -> PathBuf {
+        self.root.join(key.relative_path())
+    }
+
+    fn get(&self, key: &ThumbKey) -> Option<Vec<u8>> {
+        let now = now_ms();
+        let touch = {
+            let mut index = self.index.lock().unwrap();
+            let Some(entry) = index.entries.get_mut(key.as_str()) else {
+                index.misses += 1;
+                return None;
+            };
+            let stale = now.saturating_sub(entry.last_access_ms) > TOUCH_INTERVAL_MS;
+            entry.last_access_ms = now;
+            stale
+        };
+
+        let path = self.path_of(key);
+        match fs::read(&path) {
+            Ok(data) => {
+                if touch {
+                    let _ = File::options()
+                        .write(true)
+                        .open(&path)
+                        .and_then(|f| f.set_modified(SystemTime::now()));
+                }
+                self.index.lock().unwrap().hits += 1;
+                Some(data)
+            }
+            Err(_) => {
+                // Deleted behind our back; drop it from the index
+                let mut index = self.index.lock().unwrap();
+                if let Some(entry) = index.entries.remove(key.as_str()) {
+                    index.total_bytes -= entry.bytes;
+                }
+                index.misses += 1;
+                None
+            }
+        }
+    }
+
+    fn put(&self, key: &ThumbKey, data: &[u8]) -> io::Result<()> {
+        let path = self.path_of(key);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        // Write-then-rename so a crash never leaves a truncated thumbnail behind
+        let seq = TMP_SEQ.fetch_add(1, Ordering::Relaxed);
+        let tmp = path.with_file_name(format!("{}.{}.tmp", key.as_str(), seq));
+        fs::write(&tmp, data)?;
+        fs::rename(&tmp, &path)?;
+
+        {
+            let mut index = self.index.lock().unwrap();
+            let entry = Entry { bytes: data.len() as u64, last_access_ms: now_ms() };
+            index.total_bytes += entry.bytes;
+            index.writes += 1;
+            if let Some(old) = index.entries.insert(key.as_str().to_string(), entry) {
+                index.total_bytes -= old.bytes;
+            }
+        }
+        self.evict();
+        Ok(())
+    }
+
+    fn evict(&self) {
+        let budget = self.budget.load(Ordering::Relaxed);
+        let mut index = self.index.lock().unwrap();
+        if index.total_bytes <= budget {
+            return;
+        }
+
+        let target = (budget as f64 * EVICT_TARGET) as u64;
+        let mut by_age: Vec<(u64, String)> = index
+            .entries
+            .iter()
+            .filter(|(k, _)| !index.pinned.iter().any(|prefix| k.starts_with(prefix.as_str())))
+            .map(|(k, e)| (e.last_access_ms, k.clone()))
+            .collect();
+        by_age.sort_unstable();
+
+        let mut removed = 0;
+        for (_, name) in by_age {
+            if index.total_bytes <= target {
+                break;
+            }
+            let key = ThumbKey(name);
+            match fs::remove_file(self.path_of(&key)) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    log_error!("Failed to evict thumbnail {}: {}", key.as_str(), e);
+                    continue;
+                }
+            }
+            if let Some(entry) = index.entries.remove(key.as_str()) {
+                index.total_bytes -= entry.bytes;
+                removed += 1;
+            }
+        }
+        index.evictions += removed;
+        log_info!("Evicted {} thumbnails from the disk cache", removed);
+    }
+}
+
+// ── public API ────────────────────────────────────────────────────────────────
+
+/// Reads a thumbnail from disk, marking it recently used.
+pub fn load(key: &ThumbKey) -> Option<Vec<u8>> {
+    STORE.get(key)
+}
+
+/// Writes a thumbnail to disk, evicting least-recently-used entries if the
+/// cache goes over budget. Failures are logged; the caller still has the bytes.
+pub fn store(key: &ThumbKey, data: &[u8]) {
+    if let Err(e) = STORE.put(key, data) {
+        log_error!("Failed to write thumbnail {}: {}", key.as_str(), e);
+    }
+}
+
+/// Keeps entries whose key starts with `prefix` from being evicted until
+/// the returned guard drops, so a writer storing many related entries finds
+/// all of them when it is done. The cache may run over budget meanwhile.
+pub fn pin(prefix: String) -> Pinned {
+    STORE.index.lock().unwrap().pinned.push(prefix.clone());
+    Pinned(prefix)
+}
+
+pub struct Pinned(String);
+
+impl Drop for Pinned {
+    fn drop(&mut self) {
+        let mut index = STORE.index.lock().unwrap();
+        if let Some(i) = index.pinned.iter().position(|prefix| *prefix == self.0) {
+            index.pinned.swap_remove(i);
+        }
+    }
+}
+
+/// Deletes every stored thumbnail whose key satisfies `matches`.
+pub fn purge(matches: impl Fn(&ThumbKey) -> bool) -> usize {
+    let mut index = STORE.index.lock().unwrap();
+    let doomed: Vec<ThumbKey> = index
+        .entries
+        .keys()
+        .map(|name| ThumbKey(name.clone()))
+        .filter(|key| matches(key))
+        .collect();
+
+    let mut removed = 0;
+    for key in doomed {
+        match fs::remove_file(STORE.path_of(&key)) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => {
+                log_error!("Failed to remove thumbnail {}: {}", key.as_str(), e);
+                continue;
+            }
+        }
+        if let Some(entry) = index.entries.remove(key.as_str()) {
+            index.total_bytes -= entry.bytes;
+            removed += 1;
+        }
+    }
+    removed
+}
+
+/// Applies a new byte budget, evicting immediately if it shrank.
+pub fn set_budget_mb(mb: u64) {
+    STORE.budget.store(mb * MB, Ordering::Relaxed);
+    STORE.evict();
+}
+
+pub fn stats() -> DiskCacheStats {
+    let index = STORE.index.lock().unwrap();
+    DiskCacheStats {
+        hits: index.hits,
+        misses: index.misses,
+        writes: index.writes,
+        evictions: index.evictions,
+        entries: index.entries.len(),
+        bytes: index.total_bytes,
+        budget_bytes: STORE.budget.load(Ordering::Relaxed),
+    }
+}
+
+fn now_ms() -> u64 {
+    system_time_ms(SystemTime::now())
+}
+
+fn system_time_ms(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(quality: u8, fit: FitMode) -> ThumbSpec {
+        ThumbSpec {
+            width: 320,
+            height: 320,
+            quality,
+            format: ThumbFormat::Auto,
+            background: Background::None,
+            fit,
+            view: StudyView::default(),
+        }
+    }
+
+    #[test]
+    fn contain_profile_only_matches_its_own_quality() {
+        let q8 = spec(8, FitMode::Contain);
+        assert!(q8.matches_any_variant(&ThumbKey::new("abc", &q8)));
+        for quality in [1, 80, 85, 89, 100] {
+            let other = ThumbKey::new("abc", &spec(quality, FitMode::Contain));
+            assert!(!q8.matches_any_variant(&other), "q8 matched {}", other.as_str());
+        }
+        assert!(!q8.matches_any_variant(&ThumbKey::new("abc", &spec(8, FitMode::Cover))));
+    }
+
+    #[test]
+    fn profile_matches_backgrounds_and_views() {
+        let profile = spec(80, FitMode::Contain);
+        let variant = ThumbSpec {
+            background: Background::Color([0x12, 0xab, 0xef]),
+            view: StudyView::parse("posterize-3,mirror").unwrap(),
+            ..profile
+        };
+        assert!(profile.matches_any_variant(&ThumbKey::new("abc", &variant)));
+        assert!(profile.matches_any_variant(&ThumbKey::for_source("abc", &profile, MediaKind::AnimatedImage)));
+    }
+}
@@ -0,0 +1,289 @@
+use crate::modules::imgoptimize::{load_thumbnail, thumb_spec};
+use crate::modules::thumbcache::ThumbSpec;
+use crate::log_info;
+use base64::{engine::general_purpose, Engine as _};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Condvar, Mutex, Once};
+use tauri::{AppHandle, Emitter};
+
+// Thumbnail jobs stream each result as `thumbnail://ready` the moment it is
+// rendered, instead of answering a whole batch at once. Jobs are grouped by
+// a caller-chosen batch id (typically one per grid view) so the UI can drop
+// or re-rank everything that scrolled out of view. When a batch has nothing
+// left pending or running, `thumbnail://batch-finished` reports its totals.
+
+static SCHEDULER: Lazy<(Mutex<Scheduler>, Condvar)> =
+    Lazy::new(|| (Mutex::new(Scheduler::default()), Condvar::new()));
+static START_WORKERS: Once = Once::new();
+
+// ── types ─────────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ThumbPriority {
+    /// On screen now; rendered before anything else.
+    Visible,
+    /// Just outside the viewport; rendered when no visible work is waiting.
+    #[default]
+    Prefetch,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ThumbJobItem {
+    pub path: String,
+    #[serde(default)]
+    pub priority: ThumbPriority,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ThumbJobRequest {
+    pub batch_id: String,
+    pub items: Vec<ThumbJobItem>,
+    pub profile: Option<String>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub quality: Option<i32>,
+    pub format: Option<String>,
+    pub background: Option<String>,
+    pub fit: Option<String>,
+    /// Study view, e.g. `grayscale` or `posterize-3,mirror`.
+    pub view: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
+struct ThumbnailReadyEvent {
+    batch_id: String,
+    path: String,
+    data: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
+struct BatchFinishedEvent {
+    batch_id: String,
+    completed: usize,
+    failed: usize,
+    cancelled: bool,
+}
+
+// Queue order: priority first, then submission order within a priority.
+type QueueKey = (ThumbPriority, u64);
+
+struct Job {
+    batch_id: String,
+    path: String,
+    spec: ThumbSpec,
+}
+
+struct Batch {
+    app: AppHandle,
+    pending: HashMap<String, QueueKey>,
+    running: usize,
+    completed: usize,
+    failed: usize,
+    cancelled: bool,
+}
+
+#[derive(Default)]
+struct Scheduler {
+    queue: BTreeMap<QueueKey, Job>,
+    batches: HashMap<String, Batch>,
+    next_seq: u64,
+}
+
+impl Scheduler {
+    fn enqueue(&mut self, batch_id: &str, path: String, priority: ThumbPriority, spec: ThumbSpec) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        let batch = self.batches.get_mut(batch_id).expect("batch registered before enqueue");
+
+        // Re-submitting a queued path only updates its priority
+        if let Some(old) = batch.pending.get(&path).copied() {
+            if let Some(mut job) = self.queue.remove(&old) {
+                job.spec = spec;
+                let key = (priority, old.1);
+                batch.pending.insert(path, key);
+                self.queue.insert(key, job);
+            }
+            return;
+        }
+
+        let key = (priority, seq);
+        batch.pending.insert(path.clone(), key);
+        self.queue.insert(key, Job { batch_id: batch_id.to_string(), path, spec });
+    }
+
+    fn reprioritize(&mut self, batch_id: &str, paths: Option<&[String]>, priority: ThumbPriority) -> usize {
+        let Some(batch) = self.batches.get_mut(batch_id) else { return 0 };
+        let targets: Vec<String> = match paths {
+            Some(paths) => paths.iter().filter(|p| batch.pending.contains_key(*p)).cloned().collect(),
+            None => batch.pending.keys().cloned().collect(),
+        };
+
+        for path in &targets {
+            let old = batch.pending[path];
+            if let Some(job) = self.queue.remove(&old) {
+                let key = (priority, old.1);
+                batch.pending.insert(path.clone(), key);
+                self.queue.insert(key, job);
+            }
+        }
+        targets.len()
+    }
+
+    // Drops queued jobs; running ones finish but their results are discarded.
+    fn cancel(&mut self, batch_id: &str) -> bool {
+        let Some(batch) = self.batches.get_mut(batch_id) else { return false };
+        batch.cancelled = true;
+        for key in batch.pending.drain().map(|(_, key)| key) {
+            self.queue.remove(&key);
+        }
+        self.finish_if_idle(batch_id);
+        true
+    }
+
+    fn finish_if_idle(&mut self, batch_id: &str) {
+        let idle = self
+            .batches
+            .get(batch_id)
+            .is_some_and(|b| b.pending.is_empty() && b.running == 0);
+        if !idle {
+            return;
+        }
+        if let Some(batch) = self.batches.remove(batch_id) {
+            let _ = batch.app.emit(
+                "thumbnail://batch-finished",
+                BatchFinishedEvent {
+                    batch_id: batch_id.to_string(),
+                    completed: batch.completed,
+                    failed: batch.failed,
+                    cancelled: batch.cancelled,
+                },
+            );
+        }
+    }
+}
+
+// ── workers ───────────────────────────────────────────────────────────────────
+
+fn start_workers() {
+    START_WORKERS.call_once(|| {
+        let count = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4);
+        for i in 0..count {
+            std::thread::Builder::new()
+                .name(format!("thumb-worker-{}", i))
+                .spawn(worker_loop)
+                .expect("failed to spawn thumbnail worker");
+        }
+        log_info!("Started {} thumbnail workers", count);
+    });
+}
+
+fn worker_loop() {
+    let (lock, ready) = &*SCHEDULER;
+    loop {
+        let job = {
+            let mut scheduler = lock.lock().unwrap();
+            loop {
+                if let Some((_, job)) = scheduler.queue.pop_first() {
+                    if let Some(batch) = scheduler.batches.get_mut(&job.batch_id) {
+                        batch.pending.remove(&job.path);
+                        batch.running += 1;
+                    }
+                    break job;
+                }
+                scheduler = ready.wait(scheduler).unwrap();
+            }
+        };
+
+        let result = load_thumbnail(&job.path, &job.spec);
+
+        let mut scheduler = lock.lock().unwrap();
+        let Some(batch) = scheduler.batches.get_mut(&job.batch_id) else { continue };
+        batch.running -= 1;
+        if !batch.cancelled {
+            let event = match result {
+                Ok(bytes) => {
+                    batch.completed += 1;
+                    ThumbnailReadyEvent {
+                        batch_id: job.batch_id.clone(),
+                        path: job.path,
+                        data: Some(general_purpose::STANDARD.encode(&bytes)),
+                        error: None,
+                    }
+                }
+                Err(e) => {
+                    batch.failed += 1;
+                    ThumbnailReadyEvent {
+                        batch_id: job.batch_id.clone(),
+                        path: job.path,
+                        data: None,
+                        error: Some(e.to_string()),
+                    }
+                }
+            };
+            let _ = batch.app.emit("thumbnail://ready", event);
+        }
+        scheduler.finish_if_idle(&job.batch_id);
+    }
+}
+
+// ── commands ──────────────────────────────────────────────────────────────────
+
+/// Queues thumbnails and returns immediately; results arrive as events.
+/// Submitting to a batch id that is still running adds to it, and paths
+/// already queued in that batch just take the new priority.
+#[tauri::command]
+pub fn submit_thumbnail_jobs(app: AppHandle, request: ThumbJobRequest) -> Result<usize, String> {
+    let spec = thumb_spec(
+        request.profile.as_deref(),
+        request.width,
+        request.height,
+        request.quality,
+        request.format.as_deref(),
+        request.background.as_deref(),
+        request.fit.as_deref(),
+        request.view.as_deref(),
+    )?;
+    start_workers();
+
+    let (lock, ready) = &*SCHEDULER;
+    let mut scheduler = lock.lock().unwrap();
+    let batch = scheduler.batches.entry(request.batch_id.clone()).or_insert_with(|| Batch {
+        app,
+        pending: HashMap::new(),
+        running: 0,
+        completed: 0,
+        failed: 0,
+        cancelled: false,
+    });
+    // A cancelled batch that is still draining can be revived by new work
+    batch.cancelled = false;
+
+    let count = request.items.len();
+    for item in request.items {
+        scheduler.enqueue(&request.batch_id, item.path, item.priority, spec);
+    }
+    scheduler.finish_if_idle(&request.batch_id);
+    ready.notify_all();
+    Ok(count)
+}
+
+/// Moves queued jobs of a batch to `priority`; all of them when `paths` is
+/// omitted. Returns how many jobs were still queued and got moved.
+#[tauri::command]
+pub fn reprioritize_thumbnail_jobs(
+    batch_id: String,
+    priority: ThumbPriority,
+    paths: Option<Vec<String>>,
+) -> usize {
+    SCHEDULER.0.lock().unwrap().reprioritize(&batch_id, paths.as_deref(), priority)
+}
+
+/// Drops every queued job of a batch. Returns false for unknown or finished batches.
+#[tauri::command]
+pub fn cancel_thumbnail_jobs(batch_id: String) -> bool {
+    SCHEDULER.0.lock().unwrap().cancel(&batch_id)
+}
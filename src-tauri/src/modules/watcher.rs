@@ -0,0 +1,176 @@
+use crate::modules::config::get_config;
+use crate::modules::filecache::FileCache;
+use crate::modules::imgoptimize::evict_path_prefix;
+use crate::{log_error, log_info};
+use notify::event::{ModifyKind, RenameMode};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode};
+use notify_debouncer_full::{new_debouncer, DebounceEventResult, Debouncer, RecommendedCache};
+use once_cell::sync::{Lazy, OnceCell};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter};
+
+// Editors and browsers write in bursts (temp file, rename, chmod); collapse them.
+const DEBOUNCE: Duration = Duration::from_millis(750);
+
+static WATCHER: Lazy<Mutex<Option<Debouncer<RecommendedWatcher, RecommendedCache>>>> =
+    Lazy::new(|| Mutex::new(None));
+static APP_HANDLE: OnceCell<AppHandle> = OnceCell::new();
+
+#[derive(Serialize, Clone, Default)]
+pub struct LibraryChangedEvent {
+    pub categories: Vec<String>,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+/// Starts watching the configured library root. Called once from `setup`.
+pub fn start_library_watcher(app: &AppHandle) {
+    let _ = APP_HANDLE.set(app.clone());
+    if let Err(e) = watch_root(get_config().folderPath) {
+        log_error!("Failed to start library watcher: {}", e);
+    }
+}
+
+/// Replaces the active watcher with one on `root`, e.g. after `folderPath` changes.
+pub fn watch_root(root: PathBuf) -> Result<(), String> {
+    let mut slot = WATCHER.lock().unwrap();
+    if let Some(old) = slot.take() {
+        old.stop();
+    }
+
+    let handler_root = root.clone();
+    let mut debouncer = new_debouncer(DEBOUNCE, None, move |result: DebounceEventResult| {
+        match result {
+            Ok(events) => handle_events(&handler_root, events),
+            Err(errors) => {
+                for e in errors {
+                    log_error!("Library watcher error: {}", e);
+                }
+            }
+        }
+    })
+    .map_err(|e| format!("Failed to create watcher: {}", e))?;
+
+    debouncer
+        .watch(&root, RecursiveMode::Recursive)
+        .map_err(|e| format!("Failed to watch {}: {}", root.display(), e))?;
+
+    log_info!("Watching library root: {}", root.display());
+    *slot = Some(debouncer);
+    Ok(())
+}
+
+// ── event handling ────────────────────────────────────────────────────────────
+
+#[derive(Default)]
+struct ChangeSet {
+    touched: BTreeSet<PathBuf>,
+    removed: BTreeSet<PathBuf>,
+    renamed: Vec<(PathBuf, PathBuf)>,
+}
+
+fn handle_events(root: &Path, events: Vec<notify_debouncer_full::DebouncedEvent>) {
+    let mut changes = ChangeSet::default();
+
+    for event in events {
+        match event.kind {
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if event.paths.len() == 2 => {
+                changes.renamed.push((event.paths[0].clone(), event.paths[1].clone()));
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)) | EventKind::Remove(_) => {
+                changes.removed.extend(event.paths.iter().cloned());
+            }
+            EventKind::Create(_) | EventKind::Modify(_) => {
+                changes.touched.extend(event.paths.iter().cloned());
+            }
+            _ => {}
+        }
+    }
+
+    if changes.touched.is_empty() && changes.removed.is_empty() && changes.renamed.is_empty() {
+        return;
+    }
+
+    let Some(cache) = FileCache::get_instance() else { return };
+    let mut payload = LibraryChangedEvent::default();
+    let mut categories = BTreeSet::new();
+
+    for (from, to) in &changes.renamed {
+        if let (Some(old_cat), Some(new_cat)) = (category_of(root, from), category_of(root, to)) {
+            if to.is_file() && to.parent().and_then(Path::parent) == Some(root) {
+                if let Err(e) = cache.move_path(root, from, to, &new_cat) {
+                    log_error!("Failed to update moved image {}: {}", from.display(), e);
+                }
+            }
+            categories.insert(old_cat);
+            categories.insert(new_cat);
+        }
+        changes.removed.insert(from.clone());
+        changes.touched.insert(to.clone());
+    }
+
+    for path in &changes.removed {
+        if path.exists() {
+            continue;
+        }
+        let Some(category) = category_of(root, path) else { continue };
+        evict_path_prefix(&path.to_string_lossy());
+
+        if path.parent() == Some(root) {
+            // A whole category folder went away
+            if let Err(e) = cache.remove_category(root, &category) {
+                log_error!("Failed to drop category {} from the index: {}", category, e);
+            }
+        } else {
+            if let Err(e) = cache.remove_path(root, &category, path) {
+                log_error!("Failed to drop {} from the index: {}", path.display(), e);
+            }
+        }
+        payload.removed.push(path.to_string_lossy().to_string());
+        categories.insert(category);
+    }
+
+    for path in &changes.touched {
+        let Some(category) = category_of(root, path) else { continue };
+
+        if path.is_dir() && path.parent() == Some(root) {
+            if let Err(e) = tauri::async_runtime::block_on(cache.refresh_category(root, &category)) {
+                log_error!("Failed to index category {}: {}", category, e);
+            }
+        } else if path.is_file() && path.parent().and_then(Path::parent) == Some(root) {
+            evict_path_prefix(&path.to_string_lossy());
+            if let Err(e) = cache.upsert_file(root, &category, path) {
+                log_error!("Failed to index {}: {}", path.display(), e);
+                continue;
+            }
+        } else {
+            continue;
+        }
+        payload.added.push(path.to_string_lossy().to_string());
+        categories.insert(category);
+    }
+
+    if categories.is_empty() {
+        return;
+    }
+    payload.categories = categories.into_iter().collect();
+    emit_library_changed(payload);
+}
+
+/// Tells the UI which categories changed. A no-op until the app has started.
+pub fn emit_library_changed(event: LibraryChangedEvent) {
+    if let Some(app) = APP_HANDLE.get() {
+        let _ = app.emit("library://changed", event);
+    }
+}
+
+// Categories are the direct children of the library root; "temp" is scratch space.
+fn category_of(root: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+    let category = relative.components().next()?.as_os_str().to_string_lossy().to_string();
+    (category != "temp").then_some(category)
+}
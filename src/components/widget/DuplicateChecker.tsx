@@ -1,4 +1,4 @@
-import { useState, useEffect } from "react"
+import { useState, useEffect, useRef } from "react"
 import { invoke, convertFileSrc } from "@tauri-apps/api/core"
 import { Card, CardContent } from "@/components/ui/card"
 import { Button } from "@/components/ui/button"
@@ -32,14 +32,22 @@ interface ImageInfo {
     path: string
     category: string
     similarity?: number
+    exact?: boolean
 }
 
 interface DuplicateGroup {
     path: string
     category: string
+    exact: boolean
     duplicates: ImageInfo[]
 }
 
+type ScanScope =
+    | { kind: "library" }
+    | { kind: "categories"; categories: string[] }
+    | { kind: "files"; paths: string[] }
+    | { kind: "new_since_last_scan" }
+
 const ImageResolutions = {
     "720p": { width: 1280, height: 720 },
     "1080p": { width: 1920, height: 1080 },
@@ -66,7 +74,7 @@ const EmptyState = ({ onScan }) => (
     </div>
 )
 
-const ImagePreview = ({ src, info, onShowInFolder, onDelete, similarity, isOriginal = false, lowQuality = false }) => {
+const ImagePreview = ({ src, info, onShowInFolder, onDelete, similarity, exact = false, isOriginal = false, lowQuality = false }) => {
     const [, setShowActions] = useState(false)
     const [isLoading, setIsLoading] = useState(true)
 
@@ -147,7 +155,13 @@ const ImagePreview = ({ src, info, onShowInFolder, onDelete, similarity, isOrigi
                 </div>
 
                 {/* Similarity badge */}
-                {similarity && (
+                {exact ? (
+                    <div className="absolute top-3 left-3">
+                        <Badge variant="destructive" className="font-medium shadow-md">
+                            Identical Copy
+                        </Badge>
+                    </div>
+                ) : similarity && (
                     <div className="absolute top-3 left-3">
                         <Badge
                             variant={similarity > 0.95 ? "destructive" : similarity > 0.85 ? "default" : "secondary"}
@@ -209,7 +223,7 @@ const SideBySideView = ({ group, onShowInFolder, onDelete }) => {
             {/* Duplicates */}
             <div className="space-y-3">
                 <div className="flex items-center gap-2 sticky top-0 bg-background/95 backdrop-blur-sm py-2 z-10">
-                    <h3 className="text-lg font-medium">Potential Duplicates</h3>
+                    <h3 className="text-lg font-medium">{group.exact ? "Identical Copies" : "Potential Duplicates"}</h3>
                     <Badge variant="outline">{group.duplicates.length}</Badge>
                     <Separator className="flex-1" />
                 </div>
@@ -221,6 +235,7 @@ const SideBySideView = ({ group, onShowInFolder, onDelete }) => {
                                 src={duplicate.path || "/placeholder.svg"}
                                 info={duplicate}
                                 similarity={duplicate.similarity}
+                                exact={duplicate.exact}
                                 onShowInFolder={onShowInFolder}
                                 onDelete={onDelete}
                             />
@@ -268,7 +283,7 @@ const GalleryView = ({ group, onShowInFolder, onDelete }) => {
             {/* Duplicates */}
             <div className="space-y-3">
                 <div className="flex items-center gap-2">
-                    <h3 className="text-lg font-medium">Potential Duplicates</h3>
+                    <h3 className="text-lg font-medium">{group.exact ? "Identical Copies" : "Potential Duplicates"}</h3>
                     <Badge variant="outline">{group.duplicates.length}</Badge>
                     <Separator className="flex-1" />
                 </div>
@@ -280,6 +295,7 @@ const GalleryView = ({ group, onShowInFolder, onDelete }) => {
                             src={duplicate.path || "/placeholder.svg"}
                             info={duplicate}
                             similarity={duplicate.similarity}
+                            exact={duplicate.exact}
                             onShowInFolder={onShowInFolder}
                             onDelete={onDelete}
                             lowQuality={true}
@@ -300,7 +316,7 @@ const GalleryView = ({ group, onShowInFolder, onDelete }) => {
     )
 }
 
-const SettingsPanel = ({ threshold, setThreshold, resolution, setResolution, onScan, loading }) => (
+const SettingsPanel = ({ threshold, setThreshold, resolution, setResolution, scope, setScope, onScan, onCancel, loading }) => (
     <div className="space-y-6 p-1">
         <div>
             <h3 className="text-lg font-medium mb-2">Scan Settings</h3>
@@ -330,6 +346,19 @@ const SettingsPanel = ({ threshold, setThreshold, resolution, setResolution, onS
                 </div>
             </div>
 
+            <div className="space-y-2">
+                <label className="text-sm font-medium">Scan Scope</label>
+                <Select value={scope} onValueChange={setScope}>
+                    <SelectTrigger className="w-full">
+                        <SelectValue placeholder="Select scope" />
+                    </SelectTrigger>
+                    <SelectContent>
+                        <SelectItem value="library">Whole Library</SelectItem>
+                        <SelectItem value="new_since_last_scan">New Since Last Scan</SelectItem>
+                    </SelectContent>
+                </Select>
+            </div>
+
             <div className="space-y-2">
                 <label className="text-sm font-medium">Image Preview Quality</label>
                 <Select value={resolution} onValueChange={setResolution}>
@@ -346,11 +375,11 @@ const SettingsPanel = ({ threshold, setThreshold, resolution, setResolution, onS
             </div>
         </div>
 
-        <Button className="w-full" size="lg" onClick={onScan} disabled={loading}>
+        <Button className="w-full" size="lg" variant={loading ? "outline" : "default"} onClick={loading ? onCancel : onScan}>
             {loading ? (
                 <>
                     <Loader2 className="mr-2 h-4 w-4 animate-spin" />
-                    Scanning...
+                    Cancel Scan
                 </>
             ) : (
                 <>
@@ -372,6 +401,9 @@ const DuplicateChecker = () => {
     const [resolution, setResolution] = useState<keyof typeof ImageResolutions>("1080p")
     const [viewMode, setViewMode] = useState<"sideBySide" | "gallery">("sideBySide")
     const [settingsOpen, setSettingsOpen] = useState(false)
+    const [scope, setScope] = useState<ScanScope["kind"]>("library")
+    const [groupFilter, setGroupFilter] = useState<"all" | "exact" | "similar">("all")
+    const scanId = useRef<string | null>(null)
     const { toast } = useToast()
 
     useEffect(() => {
@@ -385,7 +417,12 @@ const DuplicateChecker = () => {
 
         window.addEventListener("keydown", handleKeyPress)
         return () => window.removeEventListener("keydown", handleKeyPress)
-    }, [currentGroupIndex, duplicates.length])
+    }, [currentGroupIndex, duplicates.length, groupFilter])
+
+    const visibleGroups = duplicates.filter(
+        (group) => groupFilter === "all" || group.exact === (groupFilter === "exact"),
+    )
+    const currentGroup = visibleGroups[Math.min(currentGroupIndex, visibleGroups.length - 1)]
 
     const checkDuplicates = async () => {
         try {
@@ -394,16 +431,20 @@ const DuplicateChecker = () => {
             setCurrentGroupIndex(0)
             setSettingsOpen(false)
 
+            scanId.current = crypto.randomUUID()
             const results = await invoke<DuplicateGroup[]>("find_duplicates", {
+                scanId: scanId.current,
                 similarityThreshold: threshold,
+                scope: { kind: scope } as ScanScope,
             })
 
             setDuplicates(results)
 
             if (results.length > 0) {
+                const exactCount = results.filter((group) => group.exact).length
                 toast({
                     title: "Scan Complete",
-                    description: `Found ${results.length} groups of similar images.`,
+                    description: `Found ${exactCount} groups of identical copies and ${results.length - exactCount} groups of similar images.`,
                     duration: 3000,
                 })
             } else {
@@ -414,6 +455,14 @@ const DuplicateChecker = () => {
                 })
             }
         } catch (error) {
+            if (error === "cancelled") {
+                toast({
+                    title: "Scan Cancelled",
+                    description: "The duplicate scan was stopped.",
+                    duration: 3000,
+                })
+                return
+            }
             console.error("Error checking duplicates:", error)
             toast({
                 title: "Error Scanning",
@@ -422,10 +471,17 @@ const DuplicateChecker = () => {
                 duration: 5000,
             })
         } finally {
+            scanId.current = null
             setLoading(false)
         }
     }
 
+    const cancelScan = async () => {
+        if (scanId.current) {
+            await invoke("cancel_duplicate_scan", { scanId: scanId.current })
+        }
+    }
+
     const handleShowInFolder = async (path: string) => {
         try {
             await invoke("show_in_folder", { path })
@@ -498,11 +554,11 @@ const DuplicateChecker = () => {
     }
 
     const navigateGroup = (direction: "prev" | "next") => {
-        if (!duplicates.length) return
+        if (!visibleGroups.length) return
 
         setCurrentGroupIndex((prev) => {
             if (direction === "next") {
-                return Math.min(prev + 1, duplicates.length - 1)
+                return Math.min(prev + 1, visibleGroups.length - 1)
             } else {
                 return Math.max(prev - 1, 0)
             }
@@ -532,22 +588,44 @@ const DuplicateChecker = () => {
                                     </Button>
 
                                     <span className="text-sm px-2">
-                    Group <span className="font-medium">{currentGroupIndex + 1}</span> of{" "}
-                                        <span className="font-medium">{duplicates.length}</span>
+                    Group <span className="font-medium">{visibleGroups.length ? currentGroupIndex + 1 : 0}</span> of{" "}
+                                        <span className="font-medium">{visibleGroups.length}</span>
                   </span>
 
                                     <Button
                                         variant="outline"
                                         size="sm"
                                         onClick={() => navigateGroup("next")}
-                                        disabled={currentGroupIndex === duplicates.length - 1}
+                                        disabled={currentGroupIndex >= visibleGroups.length - 1}
                                     >
                                         Next
                                         <ChevronRight className="h-4 w-4 ml-1" />
                                     </Button>
+
+                                    {currentGroup && (
+                                        <Badge variant={currentGroup.exact ? "destructive" : "secondary"}>
+                                            {currentGroup.exact ? "Identical Copies" : "Similar Images"}
+                                        </Badge>
+                                    )}
                                 </div>
 
                                 <div className="flex items-center gap-2">
+                                    <Select
+                                        value={groupFilter}
+                                        onValueChange={(value: "all" | "exact" | "similar") => {
+                                            setGroupFilter(value)
+                                            setCurrentGroupIndex(0)
+                                        }}
+                                    >
+                                        <SelectTrigger className="h-8 w-40">
+                                            <SelectValue />
+                                        </SelectTrigger>
+                                        <SelectContent>
+                                            <SelectItem value="all">All Groups</SelectItem>
+                                            <SelectItem value="exact">Identical Copies</SelectItem>
+                                            <SelectItem value="similar">Similar Images</SelectItem>
+                                        </SelectContent>
+                                    </Select>
                                     <div className="bg-muted/50 rounded-md p-1 flex">
                                         <Tooltip>
                                             <TooltipTrigger asChild>
@@ -594,7 +672,10 @@ const DuplicateChecker = () => {
                                                     setThreshold={setThreshold}
                                                     resolution={resolution}
                                                     setResolution={setResolution}
+                                                    scope={scope}
+                                                    setScope={setScope}
                                                     onScan={checkDuplicates}
+                                                    onCancel={cancelScan}
                                                     loading={loading}
                                                 />
                                             </div>
@@ -605,29 +686,35 @@ const DuplicateChecker = () => {
 
                             {/* Content */}
                             <CardContent className="p-6">
-                                <AnimatePresence mode="wait">
-                                    <motion.div
-                                        key={`${duplicates[currentGroupIndex].path}-${viewMode}`}
-                                        initial={{ opacity: 0 }}
-                                        animate={{ opacity: 1 }}
-                                        exit={{ opacity: 0 }}
-                                        transition={{ duration: 0.2 }}
-                                    >
-                                        {viewMode === "sideBySide" ? (
-                                            <SideBySideView
-                                                group={duplicates[currentGroupIndex]}
-                                                onShowInFolder={handleShowInFolder}
-                                                onDelete={handleDelete}
-                                            />
-                                        ) : (
-                                            <GalleryView
-                                                group={duplicates[currentGroupIndex]}
-                                                onShowInFolder={handleShowInFolder}
-                                                onDelete={handleDelete}
-                                            />
-                                        )}
-                                    </motion.div>
-                                </AnimatePresence>
+                                {!currentGroup ? (
+                                    <p className="text-sm text-muted-foreground text-center py-10">
+                                        No groups of this type were found.
+                                    </p>
+                                ) : (
+                                    <AnimatePresence mode="wait">
+                                        <motion.div
+                                            key={`${currentGroup.path}-${viewMode}`}
+                                            initial={{ opacity: 0 }}
+                                            animate={{ opacity: 1 }}
+                                            exit={{ opacity: 0 }}
+                                            transition={{ duration: 0.2 }}
+                                        >
+                                            {viewMode === "sideBySide" ? (
+                                                <SideBySideView
+                                                    group={currentGroup}
+                                                    onShowInFolder={handleShowInFolder}
+                                                    onDelete={handleDelete}
+                                                />
+                                            ) : (
+                                                <GalleryView
+                                                    group={currentGroup}
+                                                    onShowInFolder={handleShowInFolder}
+                                                    onDelete={handleDelete}
+                                                />
+                                            )}
+                                        </motion.div>
+                                    </AnimatePresence>
+                                )}
                             </CardContent>
                         </div>
                     ) : (
@@ -651,7 +738,10 @@ const DuplicateChecker = () => {
                                                 setThreshold={setThreshold}
                                                 resolution={resolution}
                                                 setResolution={setResolution}
+                                                scope={scope}
+                                                setScope={setScope}
                                                 onScan={checkDuplicates}
+                                                onCancel={cancelScan}
                                                 loading={loading}
                                             />
                                         </div>
@@ -9,6 +9,7 @@ import { Badge } from "@/components/ui/badge"
 import { Button } from "@/components/ui/button"
 
 interface ProgressInfo {
+    scan_id: string
     filename: string
     progress: number
     status: string
@@ -25,6 +26,7 @@ const DuplicateProgress = () => {
     const [isVisible, setIsVisible] = useState(false)
     const [isCollapsed, setIsCollapsed] = useState(false)
     const [progressInfo, setProgressInfo] = useState<ProgressInfo>({
+        scan_id: "",
         filename: "",
         progress: 0,
         status: "",
@@ -56,6 +58,12 @@ const DuplicateProgress = () => {
                 setTimeout(() => setIsVisible(false), 3000)
             })
             unlisten.push(finishUnlisten)
+
+            const cancelUnlisten = await listen<ProgressInfo>("dupe-check-cancelled", (event) => {
+                setProgressInfo(event.payload)
+                setIsVisible(false)
+            })
+            unlisten.push(cancelUnlisten)
         }
 
         setupListeners()
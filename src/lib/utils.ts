@@ -4,3 +4,34 @@ import { twMerge } from "tailwind-merge"
 export function cn(...inputs: ClassValue[]) {
   return twMerge(clsx(inputs))
 }
+
+// Windows and Android webviews expose custom schemes as http://<scheme>.localhost
+const LOCKERZ_ORIGIN = /windows|android/i.test(navigator.userAgent)
+  ? "http://lockerz.localhost"
+  : "lockerz://localhost"
+
+export interface ThumbOptions {
+  profile?: "grid" | "preview" | "icon" | "fullscreen" | string
+  width?: number
+  height?: number
+  quality?: number
+  format?: "auto" | "jpeg" | "webp" | "webp-lossless" | "png"
+  background?: "none" | "checkerboard" | string
+  fit?: "contain" | "cover" | "square" | "smart"
+  // Study view, e.g. "grayscale", "posterize-3,mirror", "notan-100" or "edges"
+  view?: string
+}
+
+export function thumbUrl(id: number, options: ThumbOptions = {}) {
+  const params = new URLSearchParams()
+  if (options.profile) params.set("p", options.profile)
+  if (options.width) params.set("w", String(options.width))
+  if (options.height) params.set("h", String(options.height))
+  if (options.quality) params.set("q", String(options.quality))
+  if (options.format) params.set("fmt", options.format)
+  if (options.background) params.set("bg", options.background.replace(/^#/, ""))
+  if (options.fit) params.set("fit", options.fit)
+  if (options.view) params.set("view", options.view)
+  const query = params.toString()
+  return `${LOCKERZ_ORIGIN}/thumb/${id}${query ? `?${query}` : ""}`
+}
@@ -1,20 +1,43 @@
 import {TagInfo} from "@/hooks/use-database";
 
+export type MediaKind = "image" | "animated_image" | "unsupported" | "other";
+
 export interface File {
+    id: number;
     name: string;
     category: string;
     filepath: string;
     size: number;
+    modified_ms: number;
     tags?: TagInfo[];
+    rating?: number | null;
+    media_kind: MediaKind;
+    placeholder?: string | null;
+    width?: number | null;
+    height?: number | null;
+    frame_count?: number | null;
+    duration_ms?: number | null;
     last_modified: string;
     created_at: string;
 }
 
+export interface ExifInfo {
+    make: string | null;
+    model: string | null;
+    lens: string | null;
+    taken_at: string | null;
+    iso: number | null;
+    exposure_time: string | null;
+    f_number: number | null;
+    focal_length: number | null;
+}
+
 export interface FileResponse {
     files: File[];
     current_page: number;
     total_pages: number;
     total_files: number;
+    next_cursor?: string | null;
 }
 
 export interface Category {
@@ -35,4 +58,97 @@ export interface Settings {
     batch_process?: number;
     sensitive_tags: string[];
     owner_name: string;
+    thumb_cache_mb?: number;
+    thumb_memory_mb?: number;
+    thumb_background?: string;
+    thumb_profiles?: Record<string, ThumbProfile>;
+}
+
+export interface ThumbProfile {
+    width: number;
+    height: number;
+    quality: number;
+    format?: "auto" | "jpeg" | "webp" | "png" | "";
+    fit?: "contain" | "cover" | "square" | "smart" | "";
+}
+
+export interface MemoryCacheStats {
+    hits: number;
+    misses: number;
+    insertions: number;
+    evictions: number;
+    rejections: number;
+    entries: number;
+    bytes: number;
+    budget_bytes: number;
+}
+
+export interface DiskCacheStats {
+    hits: number;
+    misses: number;
+    writes: number;
+    evictions: number;
+    entries: number;
+    bytes: number;
+    budget_bytes: number;
+}
+
+export interface RenderStats {
+    renders: number;
+    full_decodes: number;
+    scaled_decodes: number;
+    exif_thumbnails: number;
+    fallback_icons: number;
+    decode_ms: number;
+    resize_ms: number;
+    encode_ms: number;
+}
+
+export interface ThumbnailCacheStats {
+    memory: MemoryCacheStats;
+    disk: DiskCacheStats;
+    render: RenderStats;
+}
+
+export interface LibraryChangedEvent {
+    categories: string[];
+    added: string[];
+    removed: string[];
+}
+
+export type ThumbPriority = "visible" | "prefetch";
+
+export interface ThumbJobItem {
+    path: string;
+    priority?: ThumbPriority;
+}
+
+export interface ThumbnailReadyEvent {
+    batch_id: string;
+    path: string;
+    data: string | null;
+    error: string | null;
+}
+
+export interface ThumbnailBatchFinishedEvent {
+    batch_id: string;
+    completed: number;
+    failed: number;
+    cancelled: boolean;
+}
+
+export interface AtlasRect {
+    x: number;
+    y: number;
+    width: number;
+    height: number;
+}
+
+export interface ThumbnailAtlas {
+    key: string;
+    data: string;
+    width: number;
+    height: number;
+    rects: Record<string, AtlasRect>;
+    failed: Record<string, string>;
 }